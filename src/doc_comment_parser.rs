@@ -31,20 +31,8 @@ impl DocCommentType {
     pub fn edit_lines_for_comment_type(&self, lines: Vec<String>) -> Vec<String> {
         use DocCommentType::*;
         match &self {
-            OuterSingle => lines
-                .into_iter()
-                .map(|mut l| {
-                    l.insert_str(0, "/// ");
-                    l
-                })
-                .collect(),
-            InnerSingle => lines
-                .into_iter()
-                .map(|mut l| {
-                    l.insert_str(0, "//! ");
-                    l
-                })
-                .collect(),
+            OuterSingle => lines.into_iter().map(|l| prefix_line("///", l)).collect(),
+            InnerSingle => lines.into_iter().map(|l| prefix_line("//!", l)).collect(),
             OuterMulti => {
                 let mut lines = VecDeque::from(lines);
                 lines.push_front("/**".to_owned());
@@ -61,6 +49,15 @@ impl DocCommentType {
     }
 }
 
+/// Avoids leaving trailing whitespace on empty lines since we can't rely on rustfmt to remove it
+fn prefix_line(prefix: &str, line: String) -> String {
+    if line.is_empty() {
+        prefix.to_owned()
+    } else {
+        format!("{prefix} {line}")
+    }
+}
+
 #[derive(Debug)]
 pub struct InvalidDocCommentType(String);
 impl FromStr for DocCommentType {
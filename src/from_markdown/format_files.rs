@@ -0,0 +1,102 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use cli_failure::bail;
+use toml::Table;
+use tracing::{debug, info};
+use xshell::{cmd, Shell};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Formatter {
+    /// Don't format anything. doc-sync already emits doc comments with the indentation of the item they belong to.
+    None,
+    /// Run rustfmt on only the files doc-sync modified. rustfmt will use the `rustfmt.toml` in the current directory (or any parent directory).
+    Rustfmt,
+    /// Run `cargo fmt`, which formats the entire crate.
+    CargoFmt,
+    /// Run the command passed to `--format-command`.
+    Command,
+}
+
+/// The placeholder in a format command template that gets replaced with the modified files
+const FILES_PLACEHOLDER: &str = "{files}";
+
+#[wrap_match::wrap_match(log_success = false)]
+#[tracing::instrument(skip(sh))]
+pub fn format_files(
+    sh: &Shell,
+    formatter: Formatter,
+    format_command: Option<&str>,
+    modified_files: &BTreeSet<String>,
+) -> Result<(), Box<dyn Error>> {
+    if modified_files.is_empty() && !matches!(formatter, Formatter::None) {
+        info!("No files were modified, so there is nothing to format");
+        return Ok(());
+    }
+
+    match formatter {
+        Formatter::None => {}
+        Formatter::Rustfmt => {
+            let edition = get_crate_edition(sh)?;
+            for file in modified_files {
+                info!(file, "Running rustfmt");
+                // Formatting through stdin prevents rustfmt from also formatting the out-of-line modules declared in the file.
+                // The config is still found since rustfmt searches the current directory when reading from stdin.
+                let output = cmd!(sh, "rustfmt --edition {edition} --emit stdout")
+                    .stdin(sh.read_file(file)?)
+                    .output()?;
+                sh.write_file(file, output.stdout)?;
+            }
+        }
+        Formatter::CargoFmt => {
+            info!("Running cargo fmt");
+            cmd!(sh, "cargo fmt").run()?;
+        }
+        Formatter::Command => {
+            let Some(format_command) = format_command else {
+                bail!("`--formatter command` was passed, but no command was passed with `--format-command`");
+            };
+            // Split like a shell would, so arguments and paths with spaces can be quoted
+            let Some(command_parts) = shlex::split(format_command) else {
+                bail!("The command passed with `--format-command` has an unterminated quote or a trailing backslash");
+            };
+            let mut parts: Vec<String> = vec![];
+            let mut inserted_files = false;
+            for part in command_parts {
+                if part == FILES_PLACEHOLDER {
+                    parts.extend(modified_files.iter().cloned());
+                    inserted_files = true;
+                } else {
+                    parts.push(part);
+                }
+            }
+            if !inserted_files {
+                parts.extend(modified_files.iter().cloned());
+            }
+            let Some((program, args)) = parts.split_first() else {
+                bail!("The command passed with `--format-command` is empty");
+            };
+            debug!(program, args = debug(args));
+            info!("Running {program}");
+            cmd!(sh, "{program} {args...}").run()?;
+        }
+    }
+
+    Ok(())
+}
+
+#[wrap_match::wrap_match(log_success = false)]
+fn get_crate_edition(sh: &Shell) -> Result<String, Box<dyn Error>> {
+    let manifest = sh.read_file("./Cargo.toml")?;
+    let manifest: Table = Table::from_str(&manifest)?;
+    // A virtual workspace manifest has no package, in which case the default edition is used too
+    Ok(manifest
+        .get("package")
+        .and_then(|package| package.get("edition"))
+        .and_then(|e| e.as_str())
+        // Cargo defaults to 2015 if no edition is specified
+        .unwrap_or("2015")
+        .to_owned())
+}
@@ -5,20 +5,22 @@ use std::path::{Component, PathBuf};
 use cli_failure::{bail, failure_raw};
 use normalize_path::NormalizePath;
 use regex::Regex;
-use rustdoc_types::{Crate, Id};
+use rustdoc_types::{Id, ItemEnum};
 use tracing::{debug, info, warn};
 use xshell::Shell;
 
-use crate::consts::*;
+use crate::consts::{HEADER_END, HEADER_START, MODULE_DOCS_SEPARATOR};
 use crate::edit_docs;
 use crate::from_markdown::module_path::AbsoluteModulePath;
 
 use super::item_path::ItemPath;
 use super::module_path::RelativeModulePath;
+use super::CrateJson;
 
 #[derive(custom_debug_derive::Debug)]
 pub struct ItemInfo<'lt> {
     pub id: String,
+    pub markdown_file: PathBuf,
     #[debug(skip)]
     pub rustdoc_item: &'lt rustdoc_types::Item,
     #[debug(skip)]
@@ -29,50 +31,107 @@ pub struct ItemInfo<'lt> {
 }
 
 #[wrap_match::wrap_match(log_success = false)]
-#[tracing::instrument(skip(sh, json, id_capture))]
+#[tracing::instrument(skip(sh, crate_jsons, metadata_capture))]
 pub fn get_item_info<'lt>(
     sh: &Shell,
-    json: &'lt Crate,
-    id_capture: &Regex,
+    crate_jsons: &[&CrateJson<'lt>],
+    metadata_capture: &Regex,
     input_dir: &PathBuf,
     file: PathBuf,
-) -> Result<Option<ItemInfo<'lt>>, Box<dyn Error>> {
+) -> Result<Vec<ItemInfo<'lt>>, Box<dyn Error>> {
     let new_docs = sh.read_file(&file)?;
-    let captures = id_capture
+    let captures = metadata_capture
         .captures(&new_docs)
         .ok_or_else(|| failure_raw!("No rustdoc ID found"))?;
-    if captures.len() <= 1 {
-        bail!("No rustdoc ID found");
-    } else if captures.len() > 2 {
-        warn!("More than one rustdoc ID found, only the first ID will be used");
-    }
 
     let id = Id(captures[1].to_owned());
     debug!(id = id.0);
+    // The ID is from the JSON of the first feature set that includes the item
+    let feature_set = captures
+        .get(2)
+        .and_then(|feature_sets| feature_sets.as_str().split(';').next());
+    debug!(feature_set);
+    let crate_json = match feature_set {
+        Some(feature_set) if crate_jsons.len() > 1 => crate_jsons
+            .iter()
+            .find(|c| c.feature_set.as_deref() == Some(feature_set))
+            .ok_or_else(|| {
+                failure_raw!("The rustdoc JSON of feature set {feature_set} wasn't passed")
+            })?,
+        _ => crate_jsons
+            .first()
+            .expect("there should always be JSON for the crate"),
+    };
+    let (json, paths) = (crate_json.json, &crate_json.paths);
     let rustdoc_item = json.index.get(&id).ok_or_else(|| {
         failure_raw!(
             "No item found; did you re-run rustdoc in JSON output format since using to-markdown?"
         )
     })?;
-    let mut new_docs: Vec<_> = new_docs
-        .replace(&format!("{METADATA_COMMENT_PREFIX}{METADATA_ID_PREFIX}{}{METADATA_ID_SUFFIX}{METADATA_COMMENT_SUFFIX}", id.0), "")
-        .trim()
-        .lines()
-        .map(|l| l.to_owned())
-        .collect();
-    if new_docs
-        == rustdoc_item
-            .docs
-            .to_owned()
-            .unwrap_or_default()
-            .trim()
-            .lines()
-            .collect::<Vec<_>>()
-    {
+    let markdown = metadata_capture.replace(&new_docs, "");
+    // The header is generated by to-markdown and isn't part of the docs
+    let markdown = match (markdown.find(HEADER_START), markdown.find(HEADER_END)) {
+        (Some(start), Some(end)) if start < end => Cow::Owned(format!(
+            "{}{}",
+            &markdown[..start],
+            &markdown[end + HEADER_END.len()..]
+        )),
+        (None, None) => markdown,
+        _ => bail!("The header of the file is incomplete, so it can't be told apart from the docs. Restore the header or remove all of it"),
+    };
+    let rustdoc_docs = rustdoc_item.docs.as_deref().unwrap_or_default();
+    let declaration = match (&rustdoc_item.span, json.paths.get(&id)) {
+        (Some(span), Some(summary)) if matches!(rustdoc_item.inner, ItemEnum::Module(_)) => {
+            crate_json
+                .modules
+                .declaration(&span.filename, &summary.path)
+        }
+        _ => None,
+    };
+
+    // The docs of a module loaded with `mod foo;` are split between the declaration and the module file.
+    // Each part is the declaration its docs go on (or `None` for the item itself), its new docs and its current docs
+    let mut parts = vec![];
+    match declaration {
+        Some(declaration) => {
+            let rustdoc_docs: Vec<_> = rustdoc_docs.lines().collect();
+            let (outer_docs, inner_docs) =
+                rustdoc_docs.split_at(declaration.outer_doc_lines.min(rustdoc_docs.len()));
+            let (new_outer_docs, new_inner_docs) = match markdown.split_once(MODULE_DOCS_SEPARATOR)
+            {
+                Some(parts) => parts,
+                None => {
+                    if declaration.outer_doc_lines > 0 {
+                        warn!("The separator between the docs of the mod declaration and the docs of the module file was removed, so all the docs will be moved into the module file");
+                    }
+                    ("", markdown.as_ref())
+                }
+            };
+            parts.push((
+                Some(declaration),
+                docs_lines(new_outer_docs),
+                docs_lines(&outer_docs.join("\n")),
+            ));
+            parts.push((
+                None,
+                docs_lines(new_inner_docs),
+                docs_lines(&inner_docs.join("\n")),
+            ));
+        }
+        None => parts.push((None, docs_lines(&markdown), docs_lines(rustdoc_docs))),
+    }
+    parts.retain(|(_, new_docs, docs)| new_docs != docs);
+    if parts.is_empty() {
         info!("Docs have not been changed");
-        return Ok(None);
+        return Ok(vec![]);
+    }
+    if let Some(read_only) = captures.get(3) {
+        bail!(
+            "The docs of this item are read-only and can't be synced back: {}. Revert the changes to this file and edit the docs in the source instead",
+            read_only.as_str()
+        );
     }
-    edit_docs::from_markdown(&mut new_docs);
+
     let file_path = rustdoc_item
         .span
         .as_ref()
@@ -80,7 +139,6 @@ pub fn get_item_info<'lt>(
         .filename
         .display()
         .to_string();
-    let file_module_path = RelativeModulePath::from_file_path(&file_path);
     let module_path = if let Some(rustdoc_item_summary) = json.paths.get(&id) {
         Cow::Borrowed(&rustdoc_item_summary.path)
     } else {
@@ -101,22 +159,76 @@ pub fn get_item_info<'lt>(
                 }
             }
         }
-        warn!(module_path = debug(&module_path), "Item seems to be an inner item. This means that we had to estimate the module path based on the file path, so it may be incorrect");
+        let is_indexed = paths.get(&module_path).is_some_and(|entries| {
+            entries
+                .iter()
+                .any(|(_, entry)| entry.is_some_and(|entry| entry.id == id))
+        });
+        if is_indexed {
+            // Fields, variants, imports and impl blocks aren't in the paths of the JSON, but the path of their markdown file matches their path in the index
+            debug!(
+                module_path = debug(&module_path),
+                "Got the module path from the file path"
+            );
+        } else {
+            warn!(module_path = debug(&module_path), "Item seems to be an inner item. This means that we had to estimate the module path based on the file path, so it may be incorrect");
+        }
         Cow::Owned(module_path)
     };
-    let item_path = ItemPath::new(
-        &file_module_path,
-        AbsoluteModulePath(&module_path),
-        &json,
-    )
-    .ok_or_else(|| failure_raw!("Couldn't find item for part (see above); did you re-run rustdoc in JSON output format since using to-markdown?"))?;
+    // `macro_rules!` macros are scoped textually instead of by path: rustdoc puts `#[macro_export]` macros at the crate root,
+    // and macros that aren't exported can be used outside of the module they are defined in through `#[macro_use]`.
+    // Either way the definition is in the module its span is in
+    let module_path = match &rustdoc_item.span {
+        Some(span) if matches!(rustdoc_item.inner, ItemEnum::Macro(_)) => {
+            let defining_module = crate_json.modules.module_path_at(&file_path, span.begin.0);
+            let mut defining_path = vec![module_path[0].clone()];
+            defining_path.extend(defining_module.iter().cloned());
+            defining_path.extend(module_path.last().cloned());
+            debug!(
+                defining_path = debug(&defining_path),
+                "Item is a macro_rules! macro, using the path of the module it is defined in"
+            );
+            Cow::Owned(defining_path)
+        }
+        _ => module_path,
+    };
+    let mut items = vec![];
+    for (declaration, mut new_docs, _) in parts {
+        edit_docs::from_markdown(&mut new_docs);
+        let (file_path, file_module_path) = match declaration {
+            Some(declaration) => {
+                let file_path = declaration.file.display().to_string();
+                let file_module_path = crate_json.modules.module_path(&file_path);
+                (file_path, file_module_path)
+            }
+            None => (
+                file_path.clone(),
+                crate_json.modules.module_path(&file_path),
+            ),
+        };
+        let item_path = ItemPath::new(
+            &file_module_path,
+            AbsoluteModulePath(&module_path),
+            rustdoc_item,
+            paths,
+        )
+        .ok_or_else(|| failure_raw!("Couldn't find item for part (see above); did you re-run rustdoc in JSON output format since using to-markdown?"))?;
+
+        items.push(ItemInfo {
+            id: id.0.clone(),
+            markdown_file: file.clone(),
+            rustdoc_item,
+            new_docs,
+            file_path,
+            file_module_path,
+            item_path,
+        });
+    }
+
+    Ok(items)
+}
 
-    Ok(Some(ItemInfo {
-        id: id.0,
-        rustdoc_item,
-        new_docs,
-        file_path,
-        file_module_path,
-        item_path,
-    }))
+/// The lines of the docs without the leading and trailing whitespace of the docs
+fn docs_lines(docs: &str) -> Vec<String> {
+    docs.trim().lines().map(|l| l.to_owned()).collect()
 }
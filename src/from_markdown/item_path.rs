@@ -1,47 +1,213 @@
 use std::collections::HashMap;
-use std::ops::Deref;
+use std::ops::{Deref, RangeInclusive};
 
-use rustdoc_types::{Crate, ItemKind};
+use rustdoc_types::{Crate, Item, ItemEnum, ItemKind, StructKind, VariantKind};
 use tracing::{error, trace};
 
+use crate::rustdoc_json::item_name;
+
 use super::module_path::{AbsoluteModulePath, RelativeModulePath};
 
-/// Represents an item in a file
-pub struct ItemPath(HashMap<String, ItemKind>);
+/// Represents an item in a file. The parts are in order, starting with the outermost item
+pub struct ItemPath(pub(super) Vec<ItemPathPart>);
+
+#[derive(Debug)]
+pub struct ItemPathPart {
+    pub name: String,
+    pub kind: ItemKind,
+    /// The path an import imports, which tells apart the `use` items of a module
+    pub import_source: Option<String>,
+    pub origin: ItemOrigin,
+}
+
+/// Where rustdoc says an item is defined. Used to tell apart items with the same path, such as items duplicated with `#[cfg]`
+#[derive(Debug, Default)]
+pub struct ItemOrigin {
+    /// The lines of the item according to rustdoc. These may be outdated if the file has changed since rustdoc was run
+    pub lines: Option<RangeInclusive<usize>>,
+    /// The `#[cfg]` attributes of the item with all whitespace removed, sorted
+    pub cfgs: Vec<String>,
+}
+
+impl ItemOrigin {
+    pub fn new(item: &Item) -> ItemOrigin {
+        ItemOrigin {
+            lines: item.span.as_ref().map(|span| span.begin.0..=span.end.0),
+            cfgs: normalize_cfgs(item.attrs.iter().map(|a| a.as_str())),
+        }
+    }
+}
+
+/// Removes all whitespace from the `#[cfg]` attributes (ignoring other attributes) and sorts them, so attributes from rustdoc and syn can be compared
+pub fn normalize_cfgs<'a>(attrs: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut cfgs: Vec<String> = attrs
+        .map(|a| a.chars().filter(|c| !c.is_whitespace()).collect::<String>())
+        .filter(|a| a.starts_with("#[cfg("))
+        .collect();
+    cfgs.sort();
+    cfgs
+}
 
 impl ItemPath {
-    #[tracing::instrument(skip(json))]
+    /// Several items can have the same path (such as the impl blocks of a type), so the path is built from `item` up
+    /// and each part is the item that contains the part below it
+    #[tracing::instrument(skip(item, paths))]
     #[inline]
-    pub fn new(
+    pub fn new<'lt>(
         file_module_path: &RelativeModulePath,
         full_item_path: AbsoluteModulePath,
-        json: &Crate,
+        item: &'lt Item,
+        paths: &PathIndex<'lt>,
     ) -> Option<ItemPath> {
-        let mut candidates = json
-            .paths
-            .values()
-            .filter(|i| i.crate_id == 0)
-            // Exclude crate name, we already know it's the correct crate and the crate name isn't included in relative module paths
-            .filter(|i| i.path[1..].starts_with(&file_module_path));
-
-        let mut inner = HashMap::new();
-        let mut current_parts = vec![];
+        let mut inner = vec![];
+        // Skip the crate name and the module path of the file, since those parts correspond to the file itself
         let offset = 1 + file_module_path.len();
-        for part in &full_item_path[offset..] {
-            current_parts.push(part.to_owned());
-            let item = candidates
-                .find(|i| &i.path[offset..] == &current_parts)
-                .or_else(|| {
-                    error!(current_parts = debug(&current_parts), "Couldn't find item");
-                    None
-                })?;
-            inner.insert(part.to_owned(), item.kind.clone());
+        let mut child: Option<&Item> = None;
+        for i in (offset..full_item_path.len()).rev() {
+            let current_path = &full_item_path[..=i];
+            let macro_entry;
+            let entries = match paths.get(current_path) {
+                Some(entries) => entries,
+                // `macro_rules!` macros are resolved in the module they are defined in, which isn't necessarily where they are in the index
+                // (`#[macro_export]` macros are at the crate root)
+                None if child.is_none() && matches!(item.inner, ItemEnum::Macro(_)) => {
+                    macro_entry = [(ItemKind::Macro, Some(item))];
+                    &macro_entry[..]
+                }
+                None => {
+                    error!(
+                        current_parts = debug(&current_path[offset..]),
+                        "Couldn't find item"
+                    );
+                    return None;
+                }
+            };
+            let (kind, part_item) = match child {
+                None => {
+                    let (kind, _) = entries
+                        .iter()
+                        .find(|(_, entry)| entry.is_some_and(|entry| entry.id == item.id))
+                        .unwrap_or(&entries[0]);
+                    (kind, Some(item))
+                }
+                Some(child) => {
+                    let (kind, part_item) = entries
+                        .iter()
+                        .find(|(_, entry)| entry.is_some_and(|entry| contains(entry, child)))
+                        .unwrap_or(&entries[0]);
+                    (kind, *part_item)
+                }
+            };
+            inner.push(ItemPathPart {
+                name: full_item_path[i].to_owned(),
+                kind: kind.clone(),
+                import_source: match part_item.map(|i| &i.inner) {
+                    Some(ItemEnum::Import(import)) => Some(import.source.clone()),
+                    _ => None,
+                },
+                origin: part_item.map(ItemOrigin::new).unwrap_or_default(),
+            });
+            child = part_item;
         }
+        inner.reverse();
         trace!(inner = debug(&inner));
         Some(ItemPath(inner))
     }
 }
 
+/// Whether the span of `parent` contains the span of `child`
+fn contains(parent: &Item, child: &Item) -> bool {
+    match (&parent.span, &child.span) {
+        (Some(parent), Some(child)) => {
+            parent.filename == child.filename
+                && parent.begin.0 <= child.begin.0
+                && child.end.0 <= parent.end.0
+        }
+        _ => false,
+    }
+}
+
+/// The items of the local crate by their path, so we don't need to search through every path for every item.
+/// A path can have several items, for example when a type has several inherent impl blocks or a glob import has the same name as its module
+pub struct PathIndex<'lt>(HashMap<Vec<String>, Vec<(ItemKind, Option<&'lt Item>)>>);
+
+impl<'lt> PathIndex<'lt> {
+    pub fn new(json: &'lt Crate) -> PathIndex<'lt> {
+        let mut paths = PathIndex(HashMap::new());
+        for (id, summary) in json.paths.iter().filter(|(_, i)| i.crate_id == 0) {
+            let item = json.index.get(id);
+            paths.insert(summary.path.clone(), summary.kind.clone(), item);
+            if let Some(item) = item {
+                paths.add_children(json, &summary.path, item);
+            }
+        }
+        paths
+    }
+
+    /// The kinds of the items at the path and the items themselves, if they are in the index
+    pub fn get(&self, path: &[String]) -> Option<&[(ItemKind, Option<&'lt Item>)]> {
+        self.0.get(path).map(|entries| entries.as_slice())
+    }
+
+    fn insert(&mut self, path: Vec<String>, kind: ItemKind, item: Option<&'lt Item>) {
+        let entries = self.0.entry(path).or_default();
+        let is_duplicate = entries.iter().any(|(_, entry)| match (entry, item) {
+            (Some(entry), Some(item)) => entry.id == item.id,
+            _ => false,
+        });
+        if !is_duplicate {
+            entries.push((kind, item));
+        }
+    }
+
+    /// rustdoc doesn't put enum variants, fields, imports, `extern crate`s and impl blocks (and newer versions also the items of impl blocks) in `paths`,
+    /// so they are added using the path of their parent and their name in the exported file layout (see [`item_name`])
+    fn add_children(&mut self, json: &'lt Crate, parent_path: &[String], item: &'lt Item) {
+        let children: Vec<_> = match &item.inner {
+            ItemEnum::Module(item) => item.items.iter().collect(),
+            ItemEnum::Struct(item) => {
+                let fields: Vec<_> = match &item.kind {
+                    StructKind::Unit => vec![],
+                    StructKind::Tuple(fields) => fields.iter().flatten().collect(),
+                    StructKind::Plain { fields, .. } => fields.iter().collect(),
+                };
+                fields.into_iter().chain(&item.impls).collect()
+            }
+            ItemEnum::Union(item) => item.fields.iter().chain(&item.impls).collect(),
+            ItemEnum::Enum(item) => item.variants.iter().chain(&item.impls).collect(),
+            ItemEnum::Variant(item) => match &item.kind {
+                VariantKind::Plain => vec![],
+                VariantKind::Tuple(fields) => fields.iter().flatten().collect(),
+                VariantKind::Struct { fields, .. } => fields.iter().collect(),
+            },
+            ItemEnum::Impl(item) => item.items.iter().collect(),
+            _ => return,
+        };
+        for child in children.into_iter().filter_map(|id| json.index.get(id)) {
+            let kind = match (&item.inner, &child.inner) {
+                // The other children of modules are in `paths`
+                (ItemEnum::Module(_), ItemEnum::Import(_)) => ItemKind::Import,
+                (ItemEnum::Module(_), ItemEnum::ExternCrate { .. }) => ItemKind::ExternCrate,
+                (ItemEnum::Module(_), _) => continue,
+                (_, ItemEnum::Variant(_)) => ItemKind::Variant,
+                (_, ItemEnum::StructField(_)) => ItemKind::StructField,
+                (_, ItemEnum::Impl(_)) => ItemKind::Impl,
+                (_, ItemEnum::Function(_)) => ItemKind::Function,
+                (_, ItemEnum::AssocConst { .. }) => ItemKind::AssocConst,
+                (_, ItemEnum::AssocType { .. }) => ItemKind::AssocType,
+                _ => continue,
+            };
+            let Some(name) = item_name(child) else {
+                continue;
+            };
+            let mut path = parent_path.to_vec();
+            path.push(name);
+            self.add_children(json, &path, child);
+            self.insert(path, kind, Some(child));
+        }
+    }
+}
+
 impl std::fmt::Debug for ItemPath {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.0.fmt(f)
@@ -49,9 +215,77 @@ impl std::fmt::Debug for ItemPath {
 }
 
 impl Deref for ItemPath {
-    type Target = HashMap<String, ItemKind>;
+    type Target = Vec<ItemPathPart>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rustdoc_types::Span;
+
+    use super::*;
+    use crate::from_markdown::test_utils::{crate_root, rustdoc_item};
+
+    /// A `macro_rules!` macro in `src/m.rs`, such as one used by the rest of the crate through `#[macro_use]`
+    fn macro_rules(name: &str) -> Item {
+        let mut item = rustdoc_item("0:1", Some(name), ItemEnum::Macro(String::new()));
+        item.span = Some(Span {
+            filename: "src/m.rs".into(),
+            begin: (3, 1),
+            end: (3, 30),
+        });
+        item
+    }
+
+    fn path(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn resolves_macros_outside_of_the_index_in_their_module() {
+        let root = crate_root();
+        let mac = macro_rules("used");
+        let mut paths = PathIndex(HashMap::new());
+        paths.insert(path(&["krate"]), ItemKind::Module, Some(&root));
+        // rustdoc put the macro somewhere else, so its defining path isn't in the index
+        paths.insert(path(&["krate", "used"]), ItemKind::Macro, Some(&mac));
+
+        let defining_path = path(&["krate", "m", "used"]);
+        let item_path = ItemPath::new(
+            &RelativeModulePath::from_file_path("src/m.rs"),
+            AbsoluteModulePath(&defining_path),
+            &mac,
+            &paths,
+        )
+        .unwrap();
+        assert_eq!(item_path.len(), 1);
+        assert_eq!(item_path[0].name, "used");
+        assert_eq!(item_path[0].kind, ItemKind::Macro);
+        assert_eq!(item_path[0].origin.lines, Some(3..=3));
+    }
+
+    #[test]
+    fn only_macros_fall_back_to_their_module() {
+        let module = rustdoc_item(
+            "0:1",
+            Some("missing"),
+            ItemEnum::Module(rustdoc_types::Module {
+                is_crate: false,
+                items: vec![],
+                is_stripped: false,
+            }),
+        );
+        let paths = PathIndex(HashMap::new());
+        let missing_path = path(&["krate", "m", "missing"]);
+        let item_path = ItemPath::new(
+            &RelativeModulePath::from_file_path("src/m.rs"),
+            AbsoluteModulePath(&missing_path),
+            &module,
+            &paths,
+        );
+        assert!(item_path.is_none());
+    }
+}
@@ -1,81 +1,364 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 use cli_failure::bail;
 use glob::glob;
+use normalize_path::NormalizePath;
 use regex::Regex;
 use rustdoc_types::Crate;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 use xshell::{cmd, Shell};
 
 use crate::consts::*;
 use crate::get_crate_name;
-use crate::FromMarkdown;
+use crate::journal::{update_modified_snapshots, write_journal};
+use crate::parallel::parallel_map;
+use crate::rustdoc_json::{
+    crate_name, feature_set_of_json_file, read_rustdoc_json, root_file, stored_rustdoc_json,
+    subdirectory_of_stored_json,
+};
+use crate::write_files::{write_files, PendingFile};
+use crate::{FromMarkdown, EXIT_ERROR, EXIT_FORMAT_FAILED, EXIT_ITEMS_FAILED};
+
+mod format_files;
+use self::format_files::format_files;
+pub use self::format_files::Formatter;
 
 mod item_info;
-use self::item_info::get_item_info;
+use self::item_info::{get_item_info, ItemInfo};
 
 mod item_path;
+use self::item_path::PathIndex;
 
 mod module_path;
+pub use self::module_path::ModuleTree;
+
+mod report;
+pub use self::report::MessageFormat;
+use self::report::{ItemStatus, Report};
 
 mod supported_item;
 
+#[cfg(test)]
+pub mod test_utils;
+
 mod update_docs;
-use self::update_docs::update_docs;
+use self::update_docs::update_file;
+pub use self::update_docs::ModuleDocStyle;
 
 pub const FROM_MARKDOWN_MARKER: &str = ".doc_sync_from_markdown";
 
-#[wrap_match::wrap_match(disregard_result = true)]
-pub fn from_markdown(
-    sh: Shell,
+pub fn from_markdown(sh: Shell, args: FromMarkdown) -> ExitCode {
+    let message_format = args.message_format;
+    let mut report = Report::default();
+    let result = sync_docs(&sh, args, &mut report);
+    report.print(message_format, result.as_ref().err().map(|e| e.to_string()));
+
+    if result.is_err() {
+        ExitCode::from(EXIT_ERROR)
+    } else if report.has_failures() {
+        ExitCode::from(EXIT_ITEMS_FAILED)
+    } else if report.format_error.is_some() {
+        ExitCode::from(EXIT_FORMAT_FAILED)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+#[wrap_match::wrap_match(log_success = false)]
+fn sync_docs(
+    sh: &Shell,
     FromMarkdown {
         input_dir,
         allow_dirty,
+        formatter,
+        format_command,
+        module_doc_style,
+        rustdoc_json,
+        message_format: _message_format, // The report is printed by from_markdown
     }: FromMarkdown,
+    report: &mut Report,
 ) -> Result<(), Box<dyn Error>> {
-    if {
-        let git_output = cmd!(sh, "git status --short").output()?;
-        let git_output = String::from_utf8_lossy(&git_output.stdout);
-        !git_output.trim().is_empty()
-    } {
-        if allow_dirty {
-            warn!("The current repository has uncommitted changes, but `--allow-dirty` was passed so doc-sync will continue.");
-        } else {
-            bail!("There are uncommitted changes. doc-sync may cause you to lose work. Therefore, it is recommended to commit your changes (or at least run `dura capture`) before using from-markdown. To bypass this warning, use the `--allow-dirty` command line argument.");
+    let rustdoc_json = match rustdoc_json {
+        Some(rustdoc_json) => rustdoc_json,
+        None => {
+            let stored = stored_rustdoc_json(sh, &input_dir)?;
+            if stored.is_empty() {
+                // The markdown files were generated by an older version of doc-sync that didn't store the JSON
+                info!("Getting crate name");
+                let crate_name = get_crate_name(sh)?;
+                info!("Crate name is {crate_name}");
+                vec![format!("./target/doc/{crate_name}.json").into()]
+            } else {
+                stored
+            }
         }
-    }
-
-    info!("Getting crate name");
-    let crate_name = get_crate_name(&sh)?;
-    info!("Crate name is {crate_name}");
-
-    let json_path = format!("./target/doc/{crate_name}.json");
-    info!("Reading previously outputted JSON from {json_path}");
-    let json = sh.read_file(&json_path)?;
-
-    info!("Deserializing JSON");
-    let json: Crate = serde_json::from_str(&json)?;
+    };
+    let crates = read_rustdoc_json(sh, &rustdoc_json)?;
+    let absolute_input_dir = sh.current_dir().join(&input_dir).normalize();
+    let crates: Vec<_> = crates
+        .iter()
+        .zip(&rustdoc_json)
+        .map(|(json, path)| CrateJson {
+            name: crate_name(json),
+            subdirectory: subdirectory_of_stored_json(
+                &absolute_input_dir,
+                &sh.current_dir().join(path),
+            )
+            .unwrap_or_default(),
+            feature_set: feature_set_of_json_file(path),
+            json,
+            paths: PathIndex::new(json),
+            modules: root_file(json)
+                .map(|root_file| ModuleTree::new(sh, root_file))
+                .unwrap_or_default(),
+        })
+        .collect();
 
     info!("Going through input markdown files");
-    let id_capture =
-        Regex::new(&format!("{METADATA_ID_PREFIX}([^\"]*){METADATA_ID_SUFFIX}")).unwrap();
+    let metadata_capture = Regex::new(&format!(
+        "{}{}([^\"]*){}(?: {}([^\"]*){})?(?: {}([^\"]*){})?{}",
+        regex::escape(METADATA_COMMENT_PREFIX),
+        regex::escape(METADATA_ID_PREFIX),
+        regex::escape(METADATA_ID_SUFFIX),
+        regex::escape(METADATA_FEATURE_SETS_PREFIX),
+        regex::escape(METADATA_FEATURE_SETS_SUFFIX),
+        regex::escape(METADATA_READ_ONLY_PREFIX),
+        regex::escape(METADATA_READ_ONLY_SUFFIX),
+        regex::escape(METADATA_COMMENT_SUFFIX),
+    ))
+    .unwrap();
+    // Nothing is written until every edit has been planned and validated, so a failure doesn't leave the crate half updated
+    let mut items_by_file: BTreeMap<String, Vec<ItemInfo>> = BTreeMap::new();
     for file in glob(&format!("{}/**/*.md", input_dir.display()))? {
         match file {
+            Ok(file) if file.file_name().is_some_and(|name| name == INDEX_FILE_NAME) => {
+                debug!(
+                    file = display(file.display()),
+                    "Skipping generated index file"
+                );
+            }
+            Ok(file)
+                if file
+                    .file_name()
+                    .is_some_and(|name| name == SUMMARY_FILE_NAME) =>
+            {
+                debug!(
+                    file = display(file.display()),
+                    "Skipping generated table of contents"
+                );
+            }
             Ok(file) => {
                 info!(file = display(file.display()), "Found markdown file");
-                if let Ok(Some(item)) = get_item_info(&sh, &json, &id_capture, &input_dir, file) {
-                    update_docs(&sh, item);
+                let crate_jsons = crate_for_markdown_file(&crates, &input_dir, &file);
+                if crate_jsons.is_empty() {
+                    report.add(
+                        &file,
+                        None,
+                        ItemStatus::Failed(
+                            "No rustdoc JSON was passed for the crate of this file".to_owned(),
+                        ),
+                    );
+                    continue;
+                }
+                let markdown_dir = input_dir.join(&crate_jsons[0].subdirectory);
+                match get_item_info(
+                    sh,
+                    &crate_jsons,
+                    &metadata_capture,
+                    &markdown_dir,
+                    file.clone(),
+                ) {
+                    Ok(items) if items.is_empty() => report.add(&file, None, ItemStatus::Unchanged),
+                    Ok(items) => {
+                        for item in items {
+                            items_by_file
+                                .entry(item.file_path.clone())
+                                .or_default()
+                                .push(item);
+                        }
+                    }
+                    Err(e) => report.add(&file, None, ItemStatus::Failed(e.to_string())),
                 }
             }
-            Err(e) => error!("Error when finding markdown file: {e:?}"),
+            Err(e) => {
+                error!("Error when finding markdown file: {e:?}");
+                report.add(e.path(), None, ItemStatus::Failed(e.error().to_string()));
+            }
         }
     }
 
+    info!("Updating docs in {} files", items_by_file.len());
+    let mut files = vec![];
+    for (file_path, items) in items_by_file {
+        let contents = sh.read_file(&file_path)?;
+        files.push((file_path, contents, items));
+    }
+    // Each file is only parsed once and files are independent of each other, so they can be updated in parallel
+    let updated_files = parallel_map(files, |(file_path, contents, items)| {
+        let (new_contents, results) = update_file(&file_path, &contents, &items, module_doc_style);
+        (file_path, contents, new_contents, items, results)
+    });
+
+    let mut pending_files = BTreeMap::new();
+    let mut pending_items = vec![];
+    for (file_path, original_contents, new_contents, items, results) in updated_files {
+        // The docs can differ from the source file only in ways that aren't written back (such as trailing whitespace), in which case the file isn't touched at all
+        let changed = new_contents != original_contents;
+        for (item, result) in items.into_iter().zip(results) {
+            match result {
+                Ok(()) if changed => pending_items.push((item.markdown_file, file_path.clone())),
+                Ok(()) => report.add(&item.markdown_file, Some(&file_path), ItemStatus::Unchanged),
+                Err(error) => {
+                    error!(file_path, "Failed to update docs: {error}");
+                    report.add(
+                        &item.markdown_file,
+                        Some(&file_path),
+                        ItemStatus::Failed(error),
+                    );
+                }
+            }
+        }
+        if changed {
+            pending_files.insert(
+                file_path,
+                PendingFile {
+                    original_contents,
+                    new_contents,
+                },
+            );
+        }
+    }
+    if report.has_failures() {
+        error!("Some items couldn't be updated, so no files were changed");
+        report.add_all(&pending_items, || {
+            ItemStatus::Skipped("No files were changed because other items failed".to_owned())
+        });
+        return Ok(());
+    }
+
+    // Without modified files, the journal of the previous run is kept so it can still be undone
+    if pending_files.is_empty() {
+        info!("No source files need to be changed");
+    } else if let Err(e) = write_changes(sh, &input_dir, &pending_files, allow_dirty) {
+        report.add_all(&pending_items, || {
+            ItemStatus::Skipped("No files were changed because of an error".to_owned())
+        });
+        return Err(e.into());
+    }
+    report.add_all(&pending_items, || ItemStatus::Updated);
+
     info!("Creating .doc_sync_from_markdown");
     sh.write_file(input_dir.join(FROM_MARKDOWN_MARKER), "This file is created to tell doc-sync's to-markdown subcommand that it's probably safe to overwrite the generated markdown files as a safeguard against losing work.")?;
 
-    info!("Running rustfmt");
-    cmd!(sh, "cargo fmt").run()?;
+    let modified_files: BTreeSet<_> = pending_files.into_keys().collect();
+    // The docs have already been written, so a formatter failure doesn't mean from-markdown couldn't finish
+    if let Err(e) = format_files(sh, formatter, format_command.as_deref(), &modified_files) {
+        report.format_error = Some(e.to_string());
+    }
+    if !modified_files.is_empty() && !matches!(formatter, Formatter::None) {
+        // Otherwise undo would think the formatted files were changed after from-markdown modified them
+        update_modified_snapshots(sh)?;
+    }
+
+    Ok(())
+}
+
+/// The rustdoc JSON of a crate for one feature set
+pub struct CrateJson<'json> {
+    pub name: &'json str,
+    /// The directory in the input directory the markdown files of the crate are in. Only targets that aren't libraries have one
+    pub subdirectory: PathBuf,
+    pub feature_set: Option<String>,
+    pub json: &'json Crate,
+    pub paths: PathIndex<'json>,
+    pub modules: ModuleTree,
+}
+
+/// Each crate is in its own directory named after the crate (the crate root's markdown file is next to it), so the first part of the path is the crate name.
+/// Targets that aren't libraries are in a subdirectory of the input directory first.
+/// Returns the JSON of every feature set of the crate
+fn crate_for_markdown_file<'lt, 'json>(
+    crates: &'lt [CrateJson<'json>],
+    input_dir: &Path,
+    file: &Path,
+) -> Vec<&'lt CrateJson<'json>> {
+    let Ok(relative_path) = file
+        .normalize()
+        .strip_prefix(input_dir.normalize())
+        .map(|p| p.to_path_buf())
+    else {
+        return vec![];
+    };
+    let Some(subdirectory) = crates
+        .iter()
+        .map(|c| &c.subdirectory)
+        .filter(|s| relative_path.starts_with(s))
+        .max_by_key(|s| s.components().count())
+    else {
+        return vec![];
+    };
+    let crates: Vec<_> = crates
+        .iter()
+        .filter(|c| &c.subdirectory == subdirectory)
+        .collect();
+    if crates.iter().all(|c| c.name == crates[0].name) {
+        return crates;
+    }
+    let Some(first_component) = relative_path
+        .strip_prefix(subdirectory)
+        .ok()
+        .and_then(|f| f.components().next())
+        .and_then(|c| c.as_os_str().to_str())
+    else {
+        return vec![];
+    };
+    let crate_name = first_component.split('~').next().unwrap();
+    crates
+        .into_iter()
+        .filter(|c| c.name == crate_name)
+        .collect()
+}
+
+/// Checks for uncommitted changes, snapshots the files into the journal and then writes them
+#[wrap_match::wrap_match(log_success = false)]
+fn write_changes(
+    sh: &Shell,
+    input_dir: &Path,
+    pending_files: &BTreeMap<String, PendingFile>,
+    allow_dirty: bool,
+) -> Result<(), Box<dyn Error>> {
+    check_uncommitted_changes(sh, pending_files.keys().collect(), allow_dirty)?;
+    write_journal(sh, input_dir, pending_files)?;
+    write_files(sh, pending_files)?;
+    Ok(())
+}
+
+/// Only checks the files that are about to be modified, since changes to other files can't be lost
+#[wrap_match::wrap_match(log_success = false)]
+fn check_uncommitted_changes(
+    sh: &Shell,
+    files: Vec<&String>,
+    allow_dirty: bool,
+) -> Result<(), Box<dyn Error>> {
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let Ok(git_output) = cmd!(sh, "git status --short -- {files...}").output() else {
+        warn!("Couldn't check for uncommitted changes using git, is the crate in a git repository? The modified files can still be restored with `doc-sync undo`.");
+        return Ok(());
+    };
+    let git_output = String::from_utf8_lossy(&git_output.stdout);
+    if !git_output.trim().is_empty() {
+        if allow_dirty {
+            warn!("Some of the files doc-sync is about to modify have uncommitted changes, but `--allow-dirty` was passed so doc-sync will continue.");
+        } else {
+            bail!("Some of the files doc-sync is about to modify have uncommitted changes:\n{}\nThe modified files can be restored with `doc-sync undo`, but it is still recommended to commit your changes (or at least run `dura capture`) before using from-markdown. To bypass this warning, use the `--allow-dirty` command line argument.", git_output.trim_end());
+        }
+    }
 
     Ok(())
 }
@@ -1,9 +1,20 @@
+use std::collections::HashMap;
 use std::fmt::{Debug, Display};
-use std::ops::Deref;
+use std::ops::{Deref, RangeInclusive};
+use std::path::{Path, PathBuf};
+
+use normalize_path::NormalizePath;
+use syn::spanned::Spanned;
+use syn::{Expr, Item, Lit, Meta};
+use tracing::{debug, trace, warn};
+use xshell::Shell;
+
+use super::update_docs::docs_from_attrs;
 
 /// Relative to `crate`
 pub struct RelativeModulePath(Vec<String>);
 impl RelativeModulePath {
+    /// Guesses the module path based on the file path. Only correct for the standard layout of a library, see [`ModuleTree`]
     pub fn from_file_path(file_path: &str) -> RelativeModulePath {
         let mut file_module_path = vec![];
         for part in file_path.split("/") {
@@ -25,6 +36,265 @@ impl RelativeModulePath {
         RelativeModulePath(file_module_path)
     }
 }
+
+/// The module path of every file of a crate, found by following the `mod` declarations (including `#[path]`) and `include!`s from the crate root
+#[derive(Debug, Default)]
+pub struct ModuleTree {
+    files: HashMap<PathBuf, Vec<String>>,
+    /// `include!`s we couldn't resolve (such as `include!(concat!(env!("OUT_DIR"), "/generated.rs"))`), with the module path they're in
+    /// and the last string literal in the macro, which is usually the end of the file path
+    unresolved_includes: Vec<(Vec<String>, String)>,
+    /// The `mod foo;` declaration of every file that is a module, by the file of the module
+    declarations: HashMap<PathBuf, ModuleDeclaration>,
+    /// The inline modules of every file, by the file they are in
+    inline_modules: HashMap<PathBuf, Vec<InlineModule>>,
+}
+
+#[derive(Debug)]
+struct InlineModule {
+    lines: RangeInclusive<usize>,
+    module_path: Vec<String>,
+}
+
+/// A `mod foo;` declaration. rustdoc merges its outer docs with the inner docs of the module file, so we need to know where each part came from
+#[derive(Debug)]
+pub struct ModuleDeclaration {
+    /// The file the declaration is in
+    pub file: PathBuf,
+    /// The number of lines of the outer docs of the declaration, which come before the inner docs of the module file in rustdoc's docs
+    pub outer_doc_lines: usize,
+}
+
+impl ModuleTree {
+    #[tracing::instrument(skip(sh))]
+    pub fn new(sh: &Shell, root_file: &Path) -> ModuleTree {
+        let mut tree = ModuleTree::default();
+        tree.visit_file(sh, root_file, vec![], true);
+        debug!(files = tree.files.len(), "Built module tree");
+        tree
+    }
+
+    /// The module path of the file. Files that aren't part of the tree fall back to [`RelativeModulePath::from_file_path`]
+    pub fn module_path(&self, file_path: &str) -> RelativeModulePath {
+        let normalized = Path::new(file_path).normalize();
+        if let Some(module_path) = self.files.get(&normalized) {
+            return RelativeModulePath(module_path.clone());
+        }
+        let mut includes = self.unresolved_includes.iter().filter(|(_, suffix)| {
+            !suffix.is_empty() && file_path.ends_with(suffix.trim_start_matches(['/', '\\']))
+        });
+        if let (Some((module_path, _)), None) = (includes.next(), includes.next()) {
+            debug!(
+                file_path,
+                module_path = debug(module_path),
+                "File is included by an include! we couldn't resolve"
+            );
+            return RelativeModulePath(module_path.clone());
+        }
+        warn!(file_path, "The file isn't reachable through mod declarations from the crate root, so its module path has to be guessed from the file path");
+        RelativeModulePath::from_file_path(file_path)
+    }
+
+    /// The `mod foo;` declaration of the module at `item_path` (which starts with the crate name), if the module is the file at `file_path`.
+    /// Inline modules and the crate root don't have one
+    pub fn declaration(
+        &self,
+        file_path: &Path,
+        item_path: &[String],
+    ) -> Option<&ModuleDeclaration> {
+        let file_path = file_path.normalize();
+        let module_path = self.files.get(&file_path)?;
+        if item_path.get(1..) != Some(module_path.as_slice()) {
+            return None;
+        }
+        self.declarations.get(&file_path)
+    }
+
+    /// The module path of the module the line of the file is in, which is the innermost inline module containing the line or the module of the file.
+    /// Used for items rustdoc reports in another module than where they are defined, such as `#[macro_export]` macros
+    pub fn module_path_at(&self, file_path: &str, line: usize) -> RelativeModulePath {
+        let inline_module = self
+            .inline_modules
+            .get(&Path::new(file_path).normalize())
+            .into_iter()
+            .flatten()
+            .filter(|module| module.lines.contains(&line))
+            .min_by_key(|module| module.lines.end() - module.lines.start());
+        match inline_module {
+            Some(module) => RelativeModulePath(module.module_path.clone()),
+            None => self.module_path(file_path),
+        }
+    }
+
+    /// `mod_rs` is true for files that own their directory (crate roots, `mod.rs` files and files loaded with `#[path]`)
+    fn visit_file(&mut self, sh: &Shell, file: &Path, module_path: Vec<String>, mod_rs: bool) {
+        let file = file.normalize();
+        trace!(
+            file = display(file.display()),
+            module_path = debug(&module_path),
+            "Visiting file"
+        );
+        let contents = match sh.read_file(&file) {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!(
+                    file = display(file.display()),
+                    "Couldn't read module file: {e}"
+                );
+                return;
+            }
+        };
+        let syn_file = match syn::parse_file(&contents) {
+            Ok(syn_file) => syn_file,
+            Err(e) => {
+                warn!(
+                    file = display(file.display()),
+                    "Couldn't parse module file: {e}"
+                );
+                return;
+            }
+        };
+        self.files.insert(file.clone(), module_path.clone());
+
+        let file_dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+        // Children of files that don't own their directory are in a directory named after the file
+        let child_dir = if mod_rs {
+            file_dir.clone()
+        } else {
+            file_dir.join(file.file_stem().unwrap_or_default())
+        };
+        self.visit_items(
+            sh,
+            &syn_file.items,
+            &module_path,
+            &file,
+            &child_dir,
+            &file_dir,
+        );
+    }
+
+    /// `file` is the file the items are in, `child_dir` is where the files of child modules are
+    /// and `path_dir` is what `#[path]` attributes are relative to
+    fn visit_items(
+        &mut self,
+        sh: &Shell,
+        items: &[Item],
+        module_path: &[String],
+        file: &Path,
+        child_dir: &Path,
+        path_dir: &Path,
+    ) {
+        let file_dir = file.parent().unwrap_or(Path::new(""));
+        for item in items {
+            match item {
+                Item::Mod(item) => {
+                    let name = item.ident.to_string();
+                    let name = name.trim_start_matches("r#");
+                    let mut child_module_path = module_path.to_vec();
+                    child_module_path.push(name.to_owned());
+
+                    if let Some((_, items)) = &item.content {
+                        let span = item.span();
+                        self.inline_modules
+                            .entry(file.to_path_buf())
+                            .or_default()
+                            .push(InlineModule {
+                                lines: span.start().line..=span.end().line,
+                                module_path: child_module_path.clone(),
+                            });
+                        // Inline modules are a directory for their children, including `#[path]`
+                        let dir = child_dir.join(name);
+                        self.visit_items(sh, items, &child_module_path, file, &dir, &dir);
+                        continue;
+                    }
+
+                    let (module_file, mod_rs) = if let Some(path) = path_attribute(&item.attrs) {
+                        (path_dir.join(path), true)
+                    } else if sh.path_exists(child_dir.join(format!("{name}.rs"))) {
+                        (child_dir.join(format!("{name}.rs")), false)
+                    } else {
+                        (child_dir.join(name).join("mod.rs"), true)
+                    };
+                    self.declarations.insert(
+                        module_file.normalize(),
+                        ModuleDeclaration {
+                            file: file.to_path_buf(),
+                            outer_doc_lines: docs_from_attrs(&item.attrs)
+                                .map_or(0, |docs| docs.len()),
+                        },
+                    );
+                    self.visit_file(sh, &module_file, child_module_path, mod_rs);
+                }
+                Item::Macro(item) if item.mac.path.is_ident("include") => {
+                    match item.mac.parse_body::<Expr>() {
+                        // Included files are relative to the file the include! is in and their items are in the current module
+                        Ok(Expr::Lit(expr)) => {
+                            if let Lit::Str(path) = &expr.lit {
+                                let file = file_dir.join(path.value()).normalize();
+                                self.visit_include(sh, &file, module_path, child_dir, path_dir);
+                            }
+                        }
+                        _ => {
+                            let last_literal = item
+                                .mac
+                                .tokens
+                                .to_string()
+                                .rsplit('"')
+                                .nth(1)
+                                .unwrap_or_default()
+                                .to_owned();
+                            debug!(
+                                module_path = debug(module_path),
+                                last_literal, "Couldn't resolve include!"
+                            );
+                            self.unresolved_includes
+                                .push((module_path.to_vec(), last_literal));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn visit_include(
+        &mut self,
+        sh: &Shell,
+        file: &Path,
+        module_path: &[String],
+        child_dir: &Path,
+        path_dir: &Path,
+    ) {
+        let Ok(contents) = sh.read_file(file) else {
+            warn!(
+                file = display(file.display()),
+                "Couldn't read included file"
+            );
+            return;
+        };
+        // Included files may contain expressions, so they can't always be parsed as a file
+        let Ok(syn_file) = syn::parse_file(&contents) else {
+            return;
+        };
+        self.files.insert(file.to_path_buf(), module_path.to_vec());
+        self.visit_items(sh, &syn_file.items, module_path, file, child_dir, path_dir);
+    }
+}
+
+/// The value of the `#[path = "..."]` attribute
+fn path_attribute(attrs: &[syn::Attribute]) -> Option<String> {
+    attrs.iter().find_map(|attr| match &attr.meta {
+        Meta::NameValue(meta) if meta.path.is_ident("path") => match &meta.value {
+            Expr::Lit(expr) => match &expr.lit {
+                Lit::Str(path) => Some(path.value()),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
 impl Display for RelativeModulePath {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(
@@ -70,6 +340,102 @@ impl Deref for AbsoluteModulePath<'_> {
     type Target = Vec<String>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guesses_module_paths_from_file_paths() {
+        assert!(RelativeModulePath::from_file_path("src/lib.rs").is_empty());
+        assert_eq!(
+            *RelativeModulePath::from_file_path("src/foo/mod.rs"),
+            ["foo"]
+        );
+        assert_eq!(
+            *RelativeModulePath::from_file_path("src/foo/bar.rs"),
+            ["foo", "bar"]
+        );
+    }
+
+    #[test]
+    fn follows_mod_declarations_path_attributes_and_includes() {
+        let sh = Shell::new().unwrap();
+        let dir = std::env::temp_dir().join(format!("doc-sync-module-tree-{}", std::process::id()));
+        let file = |path: &str| dir.join(path).display().to_string();
+        sh.write_file(
+            dir.join("src/lib.rs"),
+            "\
+/// Outer docs
+/// of foo
+mod foo;
+#[path = \"../other/renamed.rs\"]
+mod bar;
+mod inline {
+    mod nested;
+}
+include!(\"included.rs\");
+include!(concat!(env!(\"OUT_DIR\"), \"/generated.rs\"));
+",
+        )
+        .unwrap();
+        sh.write_file(dir.join("src/foo.rs"), "mod child;").unwrap();
+        sh.write_file(dir.join("src/foo/child.rs"), "").unwrap();
+        sh.write_file(dir.join("other/renamed.rs"), "mod child;")
+            .unwrap();
+        // Files loaded with `#[path]` own their directory
+        sh.write_file(dir.join("other/child.rs"), "").unwrap();
+        sh.write_file(dir.join("src/inline/nested.rs"), "").unwrap();
+        sh.write_file(dir.join("src/included.rs"), "mod from_include;")
+            .unwrap();
+        sh.write_file(dir.join("src/from_include.rs"), "").unwrap();
+
+        let tree = ModuleTree::new(&sh, &dir.join("src/lib.rs"));
+        assert!(tree.module_path(&file("src/lib.rs")).is_empty());
+        assert_eq!(*tree.module_path(&file("src/foo.rs")), ["foo"]);
+        assert_eq!(
+            *tree.module_path(&file("src/foo/child.rs")),
+            ["foo", "child"]
+        );
+        assert_eq!(*tree.module_path(&file("other/renamed.rs")), ["bar"]);
+        assert_eq!(*tree.module_path(&file("other/child.rs")), ["bar", "child"]);
+        assert_eq!(
+            *tree.module_path(&file("src/inline/nested.rs")),
+            ["inline", "nested"]
+        );
+        // Included files are part of the module that includes them
+        assert!(tree.module_path(&file("src/included.rs")).is_empty());
+        assert_eq!(
+            *tree.module_path(&file("src/from_include.rs")),
+            ["from_include"]
+        );
+        // Unresolved includes are matched by the end of the file path
+        assert!(tree
+            .module_path("target/debug/build/krate-0123/out/generated.rs")
+            .is_empty());
+
+        assert_eq!(*tree.module_path_at(&file("src/lib.rs"), 7), ["inline"]);
+        assert!(tree.module_path_at(&file("src/lib.rs"), 3).is_empty());
+
+        let declaration = tree
+            .declaration(
+                &dir.join("src/foo.rs"),
+                &["krate".to_owned(), "foo".to_owned()],
+            )
+            .unwrap();
+        assert_eq!(declaration.file, dir.join("src/lib.rs"));
+        assert_eq!(declaration.outer_doc_lines, 2);
+        // The file isn't the module at that path, and the crate root has no declaration
+        assert!(tree
+            .declaration(&dir.join("src/foo.rs"), &["krate".to_owned()])
+            .is_none());
+        assert!(tree
+            .declaration(&dir.join("src/lib.rs"), &["krate".to_owned()])
+            .is_none());
+
+        sh.remove_path(&dir).unwrap();
     }
 }
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde_json::json;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum MessageFormat {
+    /// A table of the markdown files that weren't unchanged, followed by a summary.
+    Human,
+    /// A single JSON object containing the result of every markdown file.
+    Json,
+}
+
+#[derive(Debug)]
+pub enum ItemStatus {
+    /// The docs were written to the source file
+    Updated,
+    /// The docs in the markdown file are the same as the docs in the source file
+    Unchanged,
+    /// The docs could have been written, but weren't (for example because another item failed)
+    Skipped(String),
+    Failed(String),
+}
+
+impl ItemStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            ItemStatus::Updated => "updated",
+            ItemStatus::Unchanged => "unchanged",
+            ItemStatus::Skipped(_) => "skipped",
+            ItemStatus::Failed(_) => "failed",
+        }
+    }
+
+    /// When a markdown file has several items, the file gets the status of the item with the highest rank
+    fn rank(&self) -> u8 {
+        match self {
+            ItemStatus::Unchanged => 0,
+            ItemStatus::Updated => 1,
+            ItemStatus::Skipped(_) => 2,
+            ItemStatus::Failed(_) => 3,
+        }
+    }
+
+    fn reason(&self) -> Option<&str> {
+        match self {
+            ItemStatus::Updated | ItemStatus::Unchanged => None,
+            ItemStatus::Skipped(reason) | ItemStatus::Failed(reason) => Some(reason),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ItemReport {
+    pub markdown_file: PathBuf,
+    /// A module with docs on its declaration has two source files
+    pub source_files: Vec<String>,
+    pub status: ItemStatus,
+}
+
+/// The result of every markdown file handled by from-markdown
+#[derive(Debug, Default)]
+pub struct Report {
+    pub items: Vec<ItemReport>,
+    /// The index in `items` of each markdown file
+    indices: HashMap<PathBuf, usize>,
+    /// The error of the formatter, which runs after the files were written
+    pub format_error: Option<String>,
+}
+
+impl Report {
+    /// The docs of a module can be split between its declaration and its file, which are two items with the same markdown file.
+    /// Every markdown file only gets one entry, with the status of the item with the highest rank
+    pub fn add(&mut self, markdown_file: &Path, source_file: Option<&str>, status: ItemStatus) {
+        let Some(&index) = self.indices.get(markdown_file) else {
+            self.indices
+                .insert(markdown_file.to_owned(), self.items.len());
+            self.items.push(ItemReport {
+                markdown_file: markdown_file.to_owned(),
+                source_files: source_file.into_iter().map(|s| s.to_owned()).collect(),
+                status,
+            });
+            return;
+        };
+
+        let item = &mut self.items[index];
+        if let Some(source_file) = source_file {
+            if !item.source_files.iter().any(|s| s == source_file) {
+                item.source_files.push(source_file.to_owned());
+            }
+        }
+        match (&item.status, status) {
+            (ItemStatus::Failed(reason), ItemStatus::Failed(other_reason))
+                if *reason != other_reason =>
+            {
+                item.status = ItemStatus::Failed(format!("{reason}; {other_reason}"));
+            }
+            (current, status) if status.rank() > current.rank() => item.status = status,
+            _ => {}
+        }
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.items
+            .iter()
+            .any(|i| matches!(i.status, ItemStatus::Failed(_)))
+    }
+
+    /// Adds every markdown file with the same status. `items` contains the markdown file and the source file of each item
+    pub fn add_all(&mut self, items: &[(PathBuf, String)], status: impl Fn() -> ItemStatus) {
+        for (markdown_file, source_file) in items {
+            self.add(markdown_file, Some(source_file), status());
+        }
+    }
+
+    fn count(&self, status: &str) -> usize {
+        self.items
+            .iter()
+            .filter(|i| i.status.name() == status)
+            .count()
+    }
+
+    /// Prints the report to stdout. `error` is the error that stopped from-markdown, if there was one
+    pub fn print(&self, message_format: MessageFormat, error: Option<String>) {
+        match message_format {
+            MessageFormat::Human => self.print_human(error),
+            MessageFormat::Json => println!("{}", self.to_json(error)),
+        }
+    }
+
+    fn print_human(&self, error: Option<String>) {
+        // Unchanged items are only counted since there are usually a lot of them
+        let rows: Vec<_> = self
+            .items
+            .iter()
+            .filter(|i| !matches!(i.status, ItemStatus::Unchanged))
+            .map(|i| {
+                let mut details = i.source_files.join(", ");
+                if let Some(reason) = i.status.reason() {
+                    if !details.is_empty() {
+                        details.push_str(": ");
+                    }
+                    details.push_str(reason);
+                }
+                (
+                    i.status.name(),
+                    i.markdown_file.display().to_string(),
+                    details,
+                )
+            })
+            .collect();
+
+        if !rows.is_empty() {
+            const STATUS_HEADER: &str = "STATUS";
+            const FILE_HEADER: &str = "MARKDOWN FILE";
+            let status_width = rows
+                .iter()
+                .map(|r| r.0.len())
+                .fold(STATUS_HEADER.len(), usize::max);
+            let file_width = rows
+                .iter()
+                .map(|r| r.1.len())
+                .fold(FILE_HEADER.len(), usize::max);
+            println!("{STATUS_HEADER:status_width$}  {FILE_HEADER:file_width$}  DETAILS");
+            for (status, file, details) in rows {
+                println!("{status:status_width$}  {file:file_width$}  {details}");
+            }
+            println!();
+        }
+
+        println!(
+            "{} updated, {} unchanged, {} skipped, {} failed",
+            self.count("updated"),
+            self.count("unchanged"),
+            self.count("skipped"),
+            self.count("failed"),
+        );
+        if let Some(error) = error {
+            println!("error: {error}");
+        }
+        if let Some(format_error) = &self.format_error {
+            println!("error: the docs were written, but the modified files couldn't be formatted: {format_error}");
+        }
+    }
+
+    fn to_json(&self, error: Option<String>) -> serde_json::Value {
+        json!({
+            "success": error.is_none() && !self.has_failures() && self.format_error.is_none(),
+            "error": error,
+            "format_error": self.format_error,
+            "summary": {
+                "updated": self.count("updated"),
+                "unchanged": self.count("unchanged"),
+                "skipped": self.count("skipped"),
+                "failed": self.count("failed"),
+            },
+            "items": self.items.iter().map(|i| json!({
+                "markdown_file": i.markdown_file.display().to_string(),
+                "source_files": i.source_files,
+                "status": i.status.name(),
+                "reason": i.status.reason(),
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_the_items_of_a_markdown_file_once() {
+        let mut report = Report::default();
+        let module = Path::new("docs/krate/module~Module.md");
+        report.add(module, Some("src/lib.rs"), ItemStatus::Unchanged);
+        report.add(module, Some("src/module.rs"), ItemStatus::Updated);
+        report.add(
+            Path::new("docs/krate~Module.md"),
+            None,
+            ItemStatus::Unchanged,
+        );
+
+        assert_eq!(report.items.len(), 2);
+        assert_eq!(
+            report.items[0].source_files,
+            ["src/lib.rs", "src/module.rs"]
+        );
+        assert_eq!(report.items[0].status.name(), "updated");
+        assert_eq!(report.to_json(None)["summary"]["updated"], 1);
+        assert_eq!(report.to_json(None)["summary"]["unchanged"], 1);
+    }
+
+    #[test]
+    fn keeps_every_failure_of_a_markdown_file() {
+        let mut report = Report::default();
+        let module = Path::new("docs/krate/module~Module.md");
+        report.add(
+            module,
+            Some("src/lib.rs"),
+            ItemStatus::Failed("first".to_owned()),
+        );
+        report.add(
+            module,
+            Some("src/module.rs"),
+            ItemStatus::Failed("second".to_owned()),
+        );
+        report.add(
+            module,
+            Some("src/module.rs"),
+            ItemStatus::Skipped("skipped".to_owned()),
+        );
+
+        assert_eq!(report.items.len(), 1);
+        assert_eq!(report.items[0].status.reason(), Some("first; second"));
+        assert!(report.has_failures());
+    }
+
+    #[test]
+    fn reports_formatter_failures_separately() {
+        let mut report = Report::default();
+        report.add(
+            Path::new("docs/krate~Module.md"),
+            Some("src/lib.rs"),
+            ItemStatus::Updated,
+        );
+        report.format_error = Some("rustfmt failed".to_owned());
+
+        let json = report.to_json(None);
+        assert_eq!(json["success"], false);
+        assert_eq!(json["error"], serde_json::Value::Null);
+        assert_eq!(json["format_error"], "rustfmt failed");
+        assert_eq!(json["summary"]["updated"], 1);
+        assert!(!report.has_failures());
+    }
+}
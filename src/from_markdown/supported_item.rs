@@ -1,11 +1,13 @@
 use rustdoc_types::ItemKind;
+use syn::{punctuated::Punctuated, Ident, Meta, Token, UseTree};
 use syn::{
-    spanned::Spanned, Field, ImplItemConst, ImplItemFn, ImplItemType, Item, ItemConst, ItemEnum,
+    spanned::Spanned, Attribute, Field, ForeignItem, ForeignItemFn, ForeignItemStatic,
+    ForeignItemType, ImplItemConst, ImplItemFn, ImplItemType, Item, ItemConst, ItemEnum,
     ItemExternCrate, ItemFn, ItemImpl, ItemMacro, ItemMod, ItemStatic, ItemStruct, ItemTrait,
     ItemTraitAlias, ItemType, ItemUnion, ItemUse, TraitItemConst, TraitItemFn, TraitItemType,
     Variant,
 };
-use tracing::error;
+use tracing::{debug, error};
 
 #[derive(Debug)]
 pub enum SupportedItem<'lt> {
@@ -35,6 +37,11 @@ pub enum SupportedItem<'lt> {
     TraitFn(&'lt TraitItemFn),
     TraitType(&'lt TraitItemType),
 
+    // Foreign items (in `extern` blocks)
+    ForeignFn(&'lt ForeignItemFn),
+    ForeignStatic(&'lt ForeignItemStatic),
+    ForeignType(&'lt ForeignItemType),
+
     // Struct/enum
     Variant(&'lt Variant),
     Field(&'lt Field),
@@ -70,6 +77,11 @@ impl<'lt> SupportedItem<'lt> {
             TraitFn(i) => i,
             TraitType(i) => i,
 
+            // Foreign items
+            ForeignFn(i) => i,
+            ForeignStatic(i) => i,
+            ForeignType(i) => i,
+
             // Struct/enum
             Variant(i) => i,
             Field(i) => i,
@@ -77,16 +89,57 @@ impl<'lt> SupportedItem<'lt> {
     }
 }
 
+impl<'lt> SupportedItem<'lt> {
+    pub fn attrs(&self) -> &'lt [Attribute] {
+        match *self {
+            // Items
+            Const(i) => &i.attrs,
+            Enum(i) => &i.attrs,
+            ExternCrate(i) => &i.attrs,
+            Fn(i) => &i.attrs,
+            Impl(i) => &i.attrs,
+            Macro(i) => &i.attrs,
+            Module(i) => &i.attrs,
+            Static(i) => &i.attrs,
+            Struct(i) => &i.attrs,
+            Trait(i) => &i.attrs,
+            TraitAlias(i) => &i.attrs,
+            Type(i) => &i.attrs,
+            Union(i) => &i.attrs,
+            Use(i) => &i.attrs,
+
+            // Implementation items
+            ImplConst(i) => &i.attrs,
+            ImplFn(i) => &i.attrs,
+            ImplType(i) => &i.attrs,
+
+            // Trait items
+            TraitConst(i) => &i.attrs,
+            TraitFn(i) => &i.attrs,
+            TraitType(i) => &i.attrs,
+
+            // Foreign items
+            ForeignFn(i) => &i.attrs,
+            ForeignStatic(i) => &i.attrs,
+            ForeignType(i) => &i.attrs,
+
+            // Struct/enum
+            Variant(i) => &i.attrs,
+            Field(i) => &i.attrs,
+        }
+    }
+}
+
 impl SupportedItem<'_> {
     #[rustfmt::skip]
     #[tracing::instrument]
-    pub fn from_item<'lt>(item: &'lt Item, part: &String, kind: &ItemKind) -> Option<SupportedItem<'lt>> {
-        match item {
+    pub fn from_item<'lt>(item: &'lt Item, part: &String, kind: &ItemKind, import_source: Option<&str>) -> Vec<SupportedItem<'lt>> {
+        let supported_item = match item {
             Item::Const(item) => if matches!(kind, ItemKind::Constant) && &item.ident.to_string() == part { Some(Const(item)) } else { None },
             Item::Enum(item) => if matches!(kind, ItemKind::Enum) && &item.ident.to_string() == part { Some(Enum(item)) } else { None },
-            Item::ExternCrate(item) => if matches!(kind, ItemKind::ExternCrate) && &item.ident.to_string() == part { Some(ExternCrate(item)) } else { None },
-            Item::Fn(item) => if matches!(kind, ItemKind::Function) && &item.sig.ident.to_string() == part { Some(Fn(item)) } else { None },
-            Item::Impl(item) => if matches!(kind, ItemKind::Impl) { Some(Impl(item)) } else { None },
+            Item::ExternCrate(item) => if matches!(kind, ItemKind::ExternCrate) && item.rename.as_ref().map_or(&item.ident, |(_, rename)| rename) == part { Some(ExternCrate(item)) } else { None },
+            Item::Fn(item) => if fn_matches(item, part, kind) { Some(Fn(item)) } else { None },
+            Item::Impl(item) => if matches!(kind, ItemKind::Impl) && &impl_name(item) == part { Some(Impl(item)) } else { None },
             Item::Macro(item) => if matches!(kind, ItemKind::Macro) && {
                 if let Some(ident) = &item.ident {
                     &ident.to_string() == part
@@ -101,11 +154,256 @@ impl SupportedItem<'_> {
             Item::TraitAlias(item) => if matches!(kind, ItemKind::TraitAlias) && &item.ident.to_string() == part { Some(TraitAlias(item)) } else { None },
             Item::Type(item) => if matches!(kind, ItemKind::Typedef) && &item.ident.to_string() == part { Some(Type(item)) } else { None },
             Item::Union(item) => if matches!(kind, ItemKind::Union) && &item.ident.to_string() == part { Some(Union(item)) } else { None },
-            Item::Use(item) => if matches!(kind, ItemKind::Import) { Some(Use(item)) } else { None },
+            Item::Use(item) => if matches!(kind, ItemKind::Import) && use_matches(item, part, import_source) { Some(Use(item)) } else { None },
+            // The items of an `extern` block are part of the module the block is in, so there can be several matching items
+            Item::ForeignMod(item) => return item.items.iter().filter_map(|i| SupportedItem::from_foreign_item(i, part, kind)).collect(),
             _ => {
                 error!("Unknown or unsupported item");
                 None
             }
+        };
+        supported_item.into_iter().collect()
+    }
+
+    #[rustfmt::skip]
+    fn from_foreign_item<'lt>(item: &'lt ForeignItem, part: &String, kind: &ItemKind) -> Option<SupportedItem<'lt>> {
+        match item {
+            ForeignItem::Fn(item) => if matches!(kind, ItemKind::Function) && &item.sig.ident.to_string() == part { Some(ForeignFn(item)) } else { None },
+            ForeignItem::Static(item) => if matches!(kind, ItemKind::Static) && &item.ident.to_string() == part { Some(ForeignStatic(item)) } else { None },
+            ForeignItem::Type(item) => if matches!(kind, ItemKind::ForeignType) && &item.ident.to_string() == part { Some(ForeignType(item)) } else { None },
+            // Such as macro calls in the `extern` block, which can't be the item we are looking for
+            _ => {
+                debug!("Skipping unsupported foreign item");
+                None
+            }
         }
     }
 }
+
+/// Proc macros are functions with a `#[proc_macro]`, `#[proc_macro_attribute]` or `#[proc_macro_derive]` attribute.
+/// rustdoc names derive macros after the derive in the attribute instead of after the function
+fn fn_matches(item: &ItemFn, part: &str, kind: &ItemKind) -> bool {
+    let attr = |name: &str| item.attrs.iter().find(|a| a.path().is_ident(name));
+    match kind {
+        ItemKind::Function => item.sig.ident == part,
+        ItemKind::Macro => attr("proc_macro").is_some() && item.sig.ident == part,
+        ItemKind::ProcAttribute => attr("proc_macro_attribute").is_some() && item.sig.ident == part,
+        ItemKind::ProcDerive => attr("proc_macro_derive")
+            .and_then(|a| {
+                a.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+                    .ok()
+            })
+            .and_then(|args| args.first().map(|derive| derive.path().is_ident(part)))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// The name rustdoc JSON items of impl blocks are exported with: `impl` for inherent impls and `impl-Trait` for trait impls
+pub fn impl_name(item: &ItemImpl) -> String {
+    match &item.trait_ {
+        Some((_, path, _)) => match path.segments.last() {
+            Some(segment) => format!("impl-{}", segment.ident),
+            None => "impl".to_owned(),
+        },
+        None => "impl".to_owned(),
+    }
+}
+
+/// The name of the type the impl block is for, if it is a path
+pub fn impl_self_ident(item: &ItemImpl) -> Option<&Ident> {
+    match &*item.self_ty {
+        syn::Type::Path(ty) => ty.path.segments.last().map(|s| &s.ident),
+        _ => None,
+    }
+}
+
+/// rustdoc has an item for every name a `use` imports (`use a::{b, c}` has two), named after the name it imports.
+/// If the path it imports is known, it has to match as well, since several `use` items can import the same name under `#[cfg]`
+fn use_matches(item: &ItemUse, part: &str, import_source: Option<&str>) -> bool {
+    let mut imports = vec![];
+    use_tree_imports(&item.tree, &mut vec![], &mut imports);
+    imports.into_iter().any(|(source, name)| {
+        name == part
+            && import_source
+                .is_none_or(|import_source| import_source.trim_start_matches("::") == source)
+    })
+}
+
+/// Collects the path and the name of every import of the use tree. Glob imports are named after the module they import from, like rustdoc does
+fn use_tree_imports(tree: &UseTree, prefix: &mut Vec<String>, imports: &mut Vec<(String, String)>) {
+    let path = |prefix: &[String], ident: &Ident| {
+        if ident == "self" {
+            prefix.join("::")
+        } else {
+            prefix
+                .iter()
+                .cloned()
+                .chain([ident.to_string()])
+                .collect::<Vec<_>>()
+                .join("::")
+        }
+    };
+    match tree {
+        UseTree::Path(tree) => {
+            prefix.push(tree.ident.to_string());
+            use_tree_imports(&tree.tree, prefix, imports);
+            prefix.pop();
+        }
+        UseTree::Name(tree) => {
+            let name = if tree.ident == "self" {
+                prefix.last().cloned().unwrap_or_default()
+            } else {
+                tree.ident.to_string()
+            };
+            imports.push((path(prefix, &tree.ident), name));
+        }
+        UseTree::Rename(tree) => imports.push((path(prefix, &tree.ident), tree.rename.to_string())),
+        UseTree::Glob(_) => imports.push((
+            prefix.join("::"),
+            prefix.last().cloned().unwrap_or_default(),
+        )),
+        UseTree::Group(tree) => {
+            for tree in &tree.items {
+                use_tree_imports(tree, prefix, imports);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'lt>(item: &'lt Item, part: &str, kind: ItemKind) -> Vec<SupportedItem<'lt>> {
+        SupportedItem::from_item(item, &part.to_owned(), &kind, None)
+    }
+
+    #[test]
+    fn finds_items_of_extern_blocks() {
+        let block =
+            syn::parse_str(r#"extern "C" { fn foo(); static foo: u8; type Foo; fn bar(); }"#)
+                .unwrap();
+        assert!(matches!(
+            find(&block, "foo", ItemKind::Function)[..],
+            [ForeignFn(_)]
+        ));
+        assert!(matches!(
+            find(&block, "foo", ItemKind::Static)[..],
+            [ForeignStatic(_)]
+        ));
+        assert!(matches!(
+            find(&block, "Foo", ItemKind::ForeignType)[..],
+            [ForeignType(_)]
+        ));
+        assert!(find(&block, "baz", ItemKind::Function).is_empty());
+    }
+
+    #[test]
+    fn finds_proc_macros_by_their_attribute() {
+        let function =
+            syn::parse_str("#[proc_macro] pub fn foo(input: TokenStream) -> TokenStream { input }")
+                .unwrap();
+        assert!(matches!(
+            find(&function, "foo", ItemKind::Macro)[..],
+            [Fn(_)]
+        ));
+        assert!(find(&function, "foo", ItemKind::ProcAttribute).is_empty());
+
+        let function = syn::parse_str("#[proc_macro_attribute] pub fn foo(attr: TokenStream, item: TokenStream) -> TokenStream { item }").unwrap();
+        assert!(matches!(
+            find(&function, "foo", ItemKind::ProcAttribute)[..],
+            [Fn(_)]
+        ));
+        assert!(find(&function, "foo", ItemKind::Macro).is_empty());
+
+        // Functions without an attribute aren't macros
+        let function = syn::parse_str("pub fn foo() {}").unwrap();
+        assert!(matches!(
+            find(&function, "foo", ItemKind::Function)[..],
+            [Fn(_)]
+        ));
+        assert!(find(&function, "foo", ItemKind::Macro).is_empty());
+    }
+
+    #[test]
+    fn finds_derive_macros_by_the_name_of_the_derive() {
+        let function = syn::parse_str("#[proc_macro_derive(MyTrait, attributes(my_attr))] pub fn derive_my_trait(input: TokenStream) -> TokenStream { input }").unwrap();
+        assert!(matches!(
+            find(&function, "MyTrait", ItemKind::ProcDerive)[..],
+            [Fn(_)]
+        ));
+        assert!(find(&function, "derive_my_trait", ItemKind::ProcDerive).is_empty());
+        assert!(find(&function, "my_attr", ItemKind::ProcDerive).is_empty());
+    }
+
+    #[test]
+    fn finds_every_import_of_a_use() {
+        let item = syn::parse_str("pub use a::{b::{self, C}, d as e, f::*};").unwrap();
+        let Item::Use(item_use) = &item else {
+            unreachable!()
+        };
+        let mut imports = vec![];
+        use_tree_imports(&item_use.tree, &mut vec![], &mut imports);
+        assert_eq!(
+            imports,
+            [
+                ("a::b".to_owned(), "b".to_owned()),
+                ("a::b::C".to_owned(), "C".to_owned()),
+                ("a::d".to_owned(), "e".to_owned()),
+                ("a::f".to_owned(), "f".to_owned()),
+            ]
+        );
+
+        assert!(matches!(find(&item, "e", ItemKind::Import)[..], [Use(_)]));
+        assert!(find(&item, "d", ItemKind::Import).is_empty());
+    }
+
+    #[test]
+    fn matches_the_source_of_imports() {
+        // Imports of the same name under different `#[cfg]`s are told apart by what they import
+        let item = syn::parse_str("pub use a::Foo;").unwrap();
+        let find = |import_source| {
+            SupportedItem::from_item(&item, &"Foo".to_owned(), &ItemKind::Import, import_source)
+                .len()
+        };
+        assert_eq!(find(Some("a::Foo")), 1);
+        assert_eq!(find(Some("::a::Foo")), 1);
+        assert_eq!(find(Some("b::Foo")), 0);
+        assert_eq!(find(None), 1);
+    }
+
+    #[test]
+    fn names_impls_like_rustdoc() {
+        let item = syn::parse_str("impl<T> Foo<T> {}").unwrap();
+        let Item::Impl(inherent) = &item else {
+            unreachable!()
+        };
+        assert_eq!(impl_name(inherent), "impl");
+        assert_eq!(impl_self_ident(inherent).unwrap(), "Foo");
+        assert!(matches!(find(&item, "impl", ItemKind::Impl)[..], [Impl(_)]));
+
+        let item = syn::parse_str("impl std::fmt::Display for crate::Foo {}").unwrap();
+        let Item::Impl(trait_impl) = &item else {
+            unreachable!()
+        };
+        assert_eq!(impl_name(trait_impl), "impl-Display");
+        assert_eq!(impl_self_ident(trait_impl).unwrap(), "Foo");
+
+        let item = syn::parse_str("impl Trait for &str {}").unwrap();
+        let Item::Impl(reference_impl) = &item else {
+            unreachable!()
+        };
+        assert!(impl_self_ident(reference_impl).is_none());
+    }
+
+    #[test]
+    fn finds_extern_crates_by_their_rename() {
+        let item = syn::parse_str("extern crate alloc as my_alloc;").unwrap();
+        assert!(matches!(
+            find(&item, "my_alloc", ItemKind::ExternCrate)[..],
+            [ExternCrate(_)]
+        ));
+        assert!(find(&item, "alloc", ItemKind::ExternCrate).is_empty());
+    }
+}
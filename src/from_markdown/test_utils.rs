@@ -0,0 +1,34 @@
+//! Helpers to build rustdoc items for tests, since most of from-markdown only looks at a few fields of them
+
+use std::collections::HashMap;
+
+use rustdoc_types::{Id, Item, ItemEnum, Module, Visibility};
+
+/// An item of the local crate without a span, docs or attributes
+pub fn rustdoc_item(id: &str, name: Option<&str>, inner: ItemEnum) -> Item {
+    Item {
+        id: Id(id.to_owned()),
+        crate_id: 0,
+        name: name.map(|name| name.to_owned()),
+        span: None,
+        visibility: Visibility::Public,
+        docs: None,
+        links: HashMap::new(),
+        attrs: vec![],
+        deprecation: None,
+        inner,
+    }
+}
+
+/// The root module of a crate without any items
+pub fn crate_root() -> Item {
+    rustdoc_item(
+        "0:0",
+        None,
+        ItemEnum::Module(Module {
+            is_crate: true,
+            items: vec![],
+            is_stripped: false,
+        }),
+    )
+}
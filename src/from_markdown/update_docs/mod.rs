@@ -1,26 +1,134 @@
 use std::error::Error;
 
+use clap::ValueEnum;
+use proc_macro2::Span;
 use syn::spanned::Spanned;
-use tracing::{debug, trace};
-use xshell::Shell;
+use syn::{AttrStyle, Attribute, Meta};
+use tracing::{debug, error, trace, warn};
 
 use crate::doc_comment_parser::DocCommentType;
 
 use super::item_info::ItemInfo;
+use super::supported_item::SupportedItem;
 
 mod resolve_item;
 use self::resolve_item::resolve_item;
 
 mod update_docs_for_span;
-use self::update_docs_for_span::{insert_new_doc_comment, update_docs_for_span};
+use self::update_docs_for_span::{
+    insert_new_doc_comment, remove_doc_attrs, update_docs_for_span, DocsEdit, LineColumnExt,
+};
 
-#[wrap_match::wrap_match(disregard_result = true)]
-#[tracing::instrument(skip(sh))]
-pub fn update_docs(sh: &Shell, item: ItemInfo) -> Result<(), Box<dyn Error>> {
+mod verify_docs;
+pub use self::verify_docs::docs_from_attrs;
+use self::verify_docs::verify_docs;
+
+/// Where new docs of inline modules go. Existing docs are always updated where they are
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ModuleDocStyle {
+    /// `///` before the module.
+    Outer,
+    /// `//!` at the top of the module's body.
+    Inner,
+}
+
+/// Updates the docs of all items in a file. The file is only parsed once before and once after editing.
+/// Returns the new contents of the file and the result for each item, in the same order as `items`.
+#[tracing::instrument(skip(file_contents, items))]
+pub fn update_file(
+    file_path: &str,
+    file_contents: &str,
+    items: &[ItemInfo],
+    module_doc_style: ModuleDocStyle,
+) -> (String, Vec<Result<(), String>>) {
+    let syn_file = match syn::parse_file(file_contents) {
+        Ok(syn_file) => syn_file,
+        Err(e) => {
+            error!("Failed to parse file: {e:?}");
+            let error = format!("Failed to parse {file_path}: {e}");
+            return (file_contents.to_owned(), vec![Err(error); items.len()]);
+        }
+    };
+
+    let mut results = vec![Ok(()); items.len()];
+    let mut edits = vec![];
+    for (i, item) in items.iter().enumerate() {
+        match update_docs(file_contents, &syn_file, item, module_doc_style) {
+            Ok(item_edits) => edits.extend(item_edits.into_iter().map(|edit| (i, edit))),
+            Err(e) => results[i] = Err(e.to_string()),
+        }
+    }
+
+    // Apply the edits from the end of the file backwards so the byte ranges of the remaining edits stay valid.
+    // Edits for the file itself go last when several edits start at the same position, so the inner doc comment ends up at the top.
+    edits.sort_by_key(|(i, edit)| {
+        (
+            std::cmp::Reverse(edit.range.start),
+            items[*i].item_path.is_empty(),
+        )
+    });
+    for pair in edits.windows(2) {
+        let [(previous_i, previous), (i, edit)] = pair else {
+            unreachable!()
+        };
+        let overlaps = edit.range.end > previous.range.start
+            || (edit.range.start == previous.range.start
+                && !(edit.range.is_empty() && previous.range.is_empty()));
+        if overlaps {
+            error!(
+                previous = debug(previous),
+                edit = debug(edit),
+                "Two edits overlap"
+            );
+            for i in [previous_i, i] {
+                results[*i] = Err(
+                    "The edit overlaps with the edit of another item in the same file".to_owned(),
+                );
+            }
+        }
+    }
+
+    let mut new_contents = file_contents.to_owned();
+    for (i, edit) in &edits {
+        if results[*i].is_ok() {
+            new_contents.replace_range(edit.range.clone(), &edit.docs);
+        }
+    }
+
+    // Make sure the file still parses and each item now has the new docs
+    match syn::parse_file(&new_contents) {
+        Ok(new_syn_file) => {
+            for (i, item) in items.iter().enumerate() {
+                if results[i].is_ok() {
+                    if let Err(e) = verify_docs(&new_syn_file, item) {
+                        results[i] = Err(e.to_string());
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            error!("Failed to parse file after updating it: {e:?}");
+            let error = format!("{file_path} couldn't be parsed after updating it: {e}");
+            for result in results.iter_mut().filter(|r| r.is_ok()) {
+                *result = Err(error.clone());
+            }
+        }
+    }
+
+    (new_contents, results)
+}
+
+/// Gets the edits that update the docs of the item. `syn_file` should be `file_contents` parsed with syn.
+#[wrap_match::wrap_match(log_success = false)]
+#[tracing::instrument(skip(file_contents, syn_file))]
+fn update_docs(
+    file_contents: &str,
+    syn_file: &syn::File,
+    item: &ItemInfo,
+    module_doc_style: ModuleDocStyle,
+) -> Result<Vec<DocsEdit>, Box<dyn Error>> {
     debug!(new_docs = item.new_docs.join("\n"));
 
-    let mut file_contents = sh.read_file(&item.file_path)?;
-    let syn_file = syn::parse_file(&file_contents)?;
     if item.item_path.is_empty() {
         // The item is the file itself
 
@@ -36,46 +144,312 @@ pub fn update_docs(sh: &Shell, item: ItemInfo) -> Result<(), Box<dyn Error>> {
 
             trace!(file_attrs_len = syn_file.attrs.len());
 
-            let mut span = syn_file.attrs[0].span();
-            if syn_file.attrs.len() > 1 {
-                for attr in &syn_file.attrs[1..] {
-                    span = span.join(attr.span()).unwrap(); // Should be safe since the attributes are guaranteed to be in the same file
-                }
-            }
-
-            update_docs_for_span(
+            let span = attrs_span(&syn_file.attrs.iter().collect::<Vec<_>>()).unwrap();
+            Ok(vec![update_docs_for_span(
                 span,
-                item.new_docs,
-                &mut file_contents,
+                item.new_docs.clone(),
+                file_contents,
                 DocCommentType::InnerSingle,
                 true,
-            );
+            )])
         } else {
             // However, if there are no attributes,
             // we just insert the doc comment at the top
 
-            insert_new_doc_comment(
+            Ok(vec![insert_new_doc_comment(
                 0,
-                item.new_docs,
-                &mut file_contents,
+                item.new_docs.clone(),
+                file_contents,
                 DocCommentType::InnerSingle,
                 true,
-            );
+            )])
         }
     } else {
         // We need to resolve the item in the file
 
-        let syn_item = resolve_item(&syn_file.items, item.item_path)?;
-        update_docs_for_span(
-            syn_item.inner().span(),
-            item.new_docs,
-            &mut file_contents,
-            DocCommentType::OuterSingle,
-            false,
+        let syn_item = resolve_item(&syn_file.items, &item.item_path)?;
+        // Modules, functions and impls can have inner docs (`//!`) at the top of their body, which syn puts in the attributes of the item too
+        let (inner_docs, outer_docs): (Vec<_>, Vec<_>) = syn_item
+            .attrs()
+            .iter()
+            .filter(|a| a.path().is_ident("doc") && matches!(a.meta, Meta::NameValue(_)))
+            .partition(|a| matches!(a.style, AttrStyle::Inner(_)));
+        let body_start = module_body_start(&syn_item, file_contents);
+        let inner = match (inner_docs.is_empty(), outer_docs.is_empty()) {
+            (false, true) => true,
+            (true, false) => false,
+            // There are no docs yet or there are docs in both places, in which case all of them are moved to one place
+            _ => module_doc_style == ModuleDocStyle::Inner && body_start.is_some(),
+        };
+        if !inner_docs.is_empty() && !outer_docs.is_empty() {
+            warn!(
+                inner,
+                "The item has both outer and inner docs, so all the docs will be moved to one of them"
+            );
+        }
+
+        let (docs, other_docs) = if inner {
+            (&inner_docs, &outer_docs)
+        } else {
+            (&outer_docs, &inner_docs)
+        };
+        let mut edits = vec![];
+        match (attrs_span(docs), body_start) {
+            (Some(span), _) => edits.push(update_docs_for_span(
+                span,
+                item.new_docs.clone(),
+                file_contents,
+                if inner {
+                    DocCommentType::InnerSingle
+                } else {
+                    DocCommentType::OuterSingle
+                },
+                false,
+            )),
+            (None, Some(body_start)) if inner => edits.push(insert_new_doc_comment(
+                body_start,
+                item.new_docs.clone(),
+                file_contents,
+                DocCommentType::InnerSingle,
+                true,
+            )),
+            // Inserting at the start of the item instead of using the span of the item, since the doc comment parser would find the docs of a child item
+            _ => edits.push(insert_new_doc_comment(
+                syn_item.inner().span().start().byte_pos(file_contents),
+                item.new_docs.clone(),
+                file_contents,
+                DocCommentType::OuterSingle,
+                false,
+            )),
+        }
+        edits.extend(remove_doc_attrs(other_docs, file_contents));
+        Ok(edits)
+    }
+}
+
+/// Combines the spans of the attributes. Returns `None` if there are no attributes
+fn attrs_span(attrs: &[&Attribute]) -> Option<Span> {
+    attrs
+        .iter()
+        .map(|a| a.span())
+        // Should be safe since the attributes are guaranteed to be in the same file
+        .reduce(|span, attr_span| span.join(attr_span).unwrap())
+}
+
+/// Where new inner docs of an inline module go, which is before the first token in its body.
+/// Returns `None` if the item isn't an inline module or its body is empty, since there is no line to put the docs on
+fn module_body_start(item: &SupportedItem, file_contents: &str) -> Option<usize> {
+    let SupportedItem::Module(module) = item else {
+        return None;
+    };
+    let (brace, _) = module.content.as_ref()?;
+    let after_brace = brace.span.open().end().byte_pos(file_contents);
+    let body = &file_contents[after_brace..];
+    let whitespace = body.len() - body.trim_start().len();
+    if body.trim_start().starts_with('}') {
+        return None;
+    }
+    Some(after_brace + whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use rustdoc_types::{Item, ItemKind};
+
+    use crate::from_markdown::item_path::{ItemOrigin, ItemPath, ItemPathPart};
+    use crate::from_markdown::module_path::RelativeModulePath;
+    use crate::from_markdown::test_utils::crate_root;
+
+    use super::*;
+
+    /// An item with the path `function` in `src/lib.rs`, or the file itself if `function` is `None`
+    fn item_info<'lt>(
+        rustdoc_item: &'lt Item,
+        function: Option<&str>,
+        new_docs: &[&str],
+    ) -> ItemInfo<'lt> {
+        let parts = function
+            .map(|name| ItemPathPart {
+                name: name.to_owned(),
+                kind: ItemKind::Function,
+                import_source: None,
+                origin: ItemOrigin::default(),
+            })
+            .into_iter()
+            .collect();
+        ItemInfo {
+            id: "0:0".to_owned(),
+            markdown_file: PathBuf::new(),
+            rustdoc_item,
+            new_docs: new_docs.iter().map(|l| l.to_string()).collect(),
+            file_path: "src/lib.rs".to_owned(),
+            file_module_path: RelativeModulePath::from_file_path("src/lib.rs"),
+            item_path: ItemPath(parts),
+        }
+    }
+
+    #[test]
+    fn applies_several_edits_to_one_file() {
+        // `update_file` doesn't use the rustdoc item, so any item will do
+        let rustdoc_item = crate_root();
+        let file = "/// Old a\nfn a() {}\n\n/// Old b\n/// more\nfn b() {}\n";
+        let items = [
+            item_info(&rustdoc_item, Some("a"), &["New a", "more"]),
+            item_info(&rustdoc_item, Some("b"), &["New b"]),
+        ];
+        let (new_contents, results) =
+            update_file("src/lib.rs", file, &items, ModuleDocStyle::Outer);
+        assert_eq!(results, vec![Ok(()), Ok(())]);
+        assert_eq!(
+            new_contents,
+            "/// New a\n/// more\nfn a() {}\n\n/// New b\nfn b() {}\n"
+        );
+    }
+
+    #[test]
+    fn rejects_overlapping_edits() {
+        let rustdoc_item = crate_root();
+        let file = "/// Old a\nfn a() {}\n\n/// Old b\nfn b() {}\n";
+        // Two markdown files for the same item replace the same doc comment
+        let items = [
+            item_info(&rustdoc_item, Some("a"), &["First"]),
+            item_info(&rustdoc_item, Some("a"), &["Second"]),
+            item_info(&rustdoc_item, Some("b"), &["New b"]),
+        ];
+        let (new_contents, results) =
+            update_file("src/lib.rs", file, &items, ModuleDocStyle::Outer);
+        assert!(results[0].is_err());
+        assert!(results[1].is_err());
+        assert_eq!(results[2], Ok(()));
+        assert_eq!(
+            new_contents,
+            "/// Old a\nfn a() {}\n\n/// New b\nfn b() {}\n"
+        );
+    }
+
+    #[test]
+    fn inserts_docs_at_the_start_of_the_file() {
+        let rustdoc_item = crate_root();
+        let file = "fn a() {}\n";
+        // Both edits insert at the start of the file, and the docs of the file itself have to come first
+        let items = [
+            item_info(&rustdoc_item, Some("a"), &["A"]),
+            item_info(&rustdoc_item, None, &["Crate"]),
+        ];
+        let (new_contents, results) =
+            update_file("src/lib.rs", file, &items, ModuleDocStyle::Outer);
+        assert_eq!(results, vec![Ok(()), Ok(())]);
+        assert_eq!(new_contents, "//! Crate\n\n/// A\nfn a() {}\n");
+    }
+
+    #[test]
+    fn replaces_docs_at_the_start_of_the_file() {
+        let rustdoc_item = crate_root();
+        let file = "//! Old crate\n\n/// Old a\nfn a() {}\n";
+        let items = [
+            item_info(&rustdoc_item, None, &["Crate", "more"]),
+            item_info(&rustdoc_item, Some("a"), &["A"]),
+        ];
+        let (new_contents, results) =
+            update_file("src/lib.rs", file, &items, ModuleDocStyle::Outer);
+        assert_eq!(results, vec![Ok(()), Ok(())]);
+        assert_eq!(new_contents, "//! Crate\n//! more\n\n/// A\nfn a() {}\n");
+    }
+
+    #[test]
+    fn updates_docs_at_the_end_of_the_file() {
+        let rustdoc_item = crate_root();
+        // Without a newline at the end of the file
+        let file = "fn a() {}\n/// Old b\nfn b() {}";
+        let items = [item_info(&rustdoc_item, Some("b"), &["New b"])];
+        let (new_contents, results) =
+            update_file("src/lib.rs", file, &items, ModuleDocStyle::Outer);
+        assert_eq!(results, vec![Ok(())]);
+        assert_eq!(new_contents, "fn a() {}\n/// New b\nfn b() {}");
+
+        let file = "fn a() {}\nfn b() {}";
+        let (new_contents, results) =
+            update_file("src/lib.rs", file, &items, ModuleDocStyle::Outer);
+        assert_eq!(results, vec![Ok(())]);
+        assert_eq!(new_contents, "fn a() {}\n/// New b\nfn b() {}");
+    }
+
+    /// The module `m` in the module `outer`
+    fn nested_module_info<'lt>(rustdoc_item: &'lt Item, new_docs: &[&str]) -> ItemInfo<'lt> {
+        let mut item = item_info(rustdoc_item, Some("m"), new_docs);
+        item.item_path.0[0].kind = ItemKind::Module;
+        item.item_path.0.insert(
+            0,
+            ItemPathPart {
+                name: "outer".to_owned(),
+                kind: ItemKind::Module,
+                import_source: None,
+                origin: ItemOrigin::default(),
+            },
         );
+        item
     }
 
-    sh.write_file(&item.file_path, file_contents)?;
+    #[test]
+    fn removes_the_lines_of_the_docs_on_the_other_side() {
+        let rustdoc_item = crate_root();
+        let items = [nested_module_info(&rustdoc_item, &["New"])];
+        let file = "mod outer {\n    /// Outer\n    /// more\n    pub mod m {\n        //! Inner\n\n        pub fn f() {}\n    }\n}\n";
+
+        let (new_contents, results) =
+            update_file("src/lib.rs", file, &items, ModuleDocStyle::Outer);
+        assert_eq!(results, vec![Ok(())]);
+        assert_eq!(
+            new_contents,
+            "mod outer {\n    /// New\n    pub mod m {\n        pub fn f() {}\n    }\n}\n"
+        );
 
-    Ok(())
+        let (new_contents, results) =
+            update_file("src/lib.rs", file, &items, ModuleDocStyle::Inner);
+        assert_eq!(results, vec![Ok(())]);
+        assert_eq!(
+            new_contents,
+            "mod outer {\n    pub mod m {\n        //! New\n\n        pub fn f() {}\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn removes_the_lines_of_the_docs_on_the_other_side_with_crlf() {
+        let rustdoc_item = crate_root();
+        let items = [nested_module_info(&rustdoc_item, &["New"])];
+        let file = "mod outer {\r\n    /// Outer\r\n    pub mod m {\r\n        //! Inner\r\n        pub fn f() {}\r\n    }\r\n}\r\n";
+        let (new_contents, results) =
+            update_file("src/lib.rs", file, &items, ModuleDocStyle::Inner);
+        assert_eq!(results, vec![Ok(())]);
+        assert_eq!(
+            new_contents,
+            "mod outer {\r\n    pub mod m {\r\n        //! New\r\n        pub fn f() {}\r\n    }\r\n}\r\n"
+        );
+    }
+
+    #[test]
+    fn removes_docs_on_the_other_side_that_share_their_line() {
+        let rustdoc_item = crate_root();
+        let mut item = item_info(&rustdoc_item, Some("m"), &["New"]);
+        item.item_path.0[0].kind = ItemKind::Module;
+        let items = [item];
+        // Only the docs are removed, not the attribute between them or the code on the same line
+        let file =
+            "/// Outer\n#[cfg(all())]\n/// more\npub mod m { //! Inner\n    pub fn f() {}\n}\n";
+        let (new_contents, results) =
+            update_file("src/lib.rs", file, &items, ModuleDocStyle::Inner);
+        assert_eq!(results, vec![Ok(())]);
+        assert_eq!(
+            new_contents,
+            "#[cfg(all())]\npub mod m { //! New\n    pub fn f() {}\n}\n"
+        );
+
+        let file = "/// Outer\npub mod m {\n    #![doc = \"Inner\"] pub fn f() {}\n}\n";
+        let (new_contents, results) =
+            update_file("src/lib.rs", file, &items, ModuleDocStyle::Outer);
+        assert_eq!(results, vec![Ok(())]);
+        assert_eq!(new_contents, "/// New\npub mod m {\n    pub fn f() {}\n}\n");
+    }
 }
@@ -2,17 +2,29 @@ use std::error::Error;
 
 use cli_failure::bail;
 use rustdoc_types::ItemKind;
-use syn::{ImplItem, Item, Stmt, TraitItem};
+use syn::{Ident, ImplItem, Item, Stmt, TraitItem};
+use tracing::trace;
 
-use crate::from_markdown::item_path::ItemPath;
-use crate::from_markdown::supported_item::SupportedItem;
+use crate::from_markdown::item_path::{normalize_cfgs, ItemOrigin, ItemPath, ItemPathPart};
+use crate::from_markdown::supported_item::{impl_name, impl_self_ident, SupportedItem};
 
+/// Resolves the item at `item_path`. If several items match a part of the path (for example because of `#[cfg]`),
+/// the rustdoc origin of the part is used to choose between them
 pub fn resolve_item<'lt>(
     candidates: &'lt Vec<Item>,
-    item_path: ItemPath,
+    item_path: &ItemPath,
 ) -> Result<SupportedItem<'lt>, Box<dyn Error>> {
     let mut parent = None;
-    for (part, kind) in item_path.iter() {
+    // The items the parent was found in. Impl blocks are next to the type they are for instead of in it
+    let mut siblings: Vec<&Item> = candidates.iter().collect();
+    for ItemPathPart {
+        name: part,
+        kind,
+        import_source,
+        origin,
+    } in item_path.iter()
+    {
+        let import_source = import_source.as_deref();
         if let Some(unwrapped_parent) = &parent {
             use SupportedItem::*;
 
@@ -21,17 +33,22 @@ pub fn resolve_item<'lt>(
             match *unwrapped_parent {
                 // Items
                 Const(_) => unreachable!(),
+                Enum(unwrapped_parent) if matches!(kind, ItemKind::Impl) => {
+                    parent = Some(pick_impl(&siblings, &unwrapped_parent.ident, part, origin)?);
+                }
                 Enum(unwrapped_parent) => {
                     if !matches!(kind, ItemKind::Variant) {
-                        bail!("{part:?} was a {kind:?} but it should be a Variant since the parent was an enum");
+                        bail!("{part:?} was a {kind:?} but it should be a Variant or Impl since the parent was an enum");
                     }
 
                     match unwrapped_parent
                         .variants
                         .iter()
-                        .find(|v| &v.ident.to_string() == part)
+                        .filter(|v| &v.ident.to_string() == part)
+                        .map(Variant)
+                        .pick(part, origin)?
                     {
-                        Some(v) => parent = Some(Variant(v)),
+                        Some(v) => parent = Some(v),
                         None => {
                             bail!(
                             "Couldn't find a variant with a name of {part:?}. Parent: {parent:#?}"
@@ -41,7 +58,7 @@ pub fn resolve_item<'lt>(
                 }
                 ExternCrate(_) => unreachable!(),
                 Fn(unwrapped_parent) => {
-                    match unwrapped_parent
+                    let items: Vec<_> = unwrapped_parent
                         .block
                         .stmts
                         .iter()
@@ -52,9 +69,8 @@ pub fn resolve_item<'lt>(
                                 None
                             }
                         })
-                        .filter_map(|i| SupportedItem::from_item(i, part, kind))
-                        .next()
-                    {
+                        .collect();
+                    match pick_item(&items, part, kind, import_source, origin)? {
                         Some(i) => parent = Some(i),
                         None => {
                             bail!(
@@ -62,6 +78,7 @@ pub fn resolve_item<'lt>(
                             )
                         }
                     }
+                    siblings = items;
                 }
                 Impl(unwrapped_parent) => {
                     if !(matches!(kind, ItemKind::AssocConst)
@@ -83,7 +100,7 @@ pub fn resolve_item<'lt>(
                                 _ => None,
                             }
                         })
-                        .next()
+                        .pick(part, origin)?
                     {
                         Some(i) => parent = Some(i),
                         None => bail!("Couldn't get item for {part:?} with kind {kind:?}. Parent: {parent:#?}"),
@@ -95,39 +112,29 @@ pub fn resolve_item<'lt>(
                         bail!("Couldn't get item for {part:?} with kind {kind:?} because the parent module had no content");
                     };
 
-                    match items
-                        .iter()
-                        .filter_map(|i| SupportedItem::from_item(i, part, kind))
-                        .next()
-                    {
+                    let items: Vec<_> = items.iter().collect();
+                    match pick_item(&items, part, kind, import_source, origin)? {
                         Some(i) => parent = Some(i),
                         None => {
                             bail!(
                                 "Couldn't get item for {part:?} with kind {kind:?}. Parent: {parent:#?}"
                             )
                         }
-                    };
+                    }
+                    siblings = items;
                 }
                 Static(_) => unreachable!(),
+                Struct(unwrapped_parent) if matches!(kind, ItemKind::Impl) => {
+                    parent = Some(pick_impl(&siblings, &unwrapped_parent.ident, part, origin)?);
+                }
                 Struct(unwrapped_parent) => {
-                    if !matches!(kind, ItemKind::StructField) {
-                        bail!("{part:?} was a {kind:?} but it should be a StructField since the parent was a struct");
-                    }
-
-                    match unwrapped_parent.fields.iter().find(|f| {
-                        if let Some(ident) = &f.ident {
-                            &ident.to_string() == part
-                        } else {
-                            false
-                        }
-                    }) {
-                        Some(f) => parent = Some(Field(f)),
-                        None => {
-                            bail!(
-                            "Couldn't find a field with a name of {part:?}. Parent: {parent:#?}"
-                        )
-                        }
-                    }
+                    parent = Some(pick_field(
+                        &unwrapped_parent.fields,
+                        part,
+                        kind,
+                        origin,
+                        "struct",
+                    )?);
                 }
                 Trait(unwrapped_parent) => {
                     if !(matches!(kind, ItemKind::AssocConst)
@@ -149,7 +156,7 @@ pub fn resolve_item<'lt>(
                                 _ => None,
                             }
                         })
-                        .next()
+                        .pick(part, origin)?
                     {
                         Some(i) => parent = Some(i),
                         None => bail!("Couldn't get item for {part:?} with kind {kind:?}. Parent: {parent:#?}"),
@@ -157,15 +164,24 @@ pub fn resolve_item<'lt>(
                 }
                 TraitAlias(_) => unreachable!(),
                 Type(_) => unreachable!(),
-                Union(_unwrapped_parent) => {
-                    bail!("Couldn't find a field with a name of {part:?} because the parent is a union. Rustdoc doesn't seem to support union field documentation. If they are classified as struct fields, please create a GitHub Issue");
+                Union(unwrapped_parent) if matches!(kind, ItemKind::Impl) => {
+                    parent = Some(pick_impl(&siblings, &unwrapped_parent.ident, part, origin)?);
+                }
+                Union(unwrapped_parent) => {
+                    parent = Some(pick_field(
+                        &unwrapped_parent.fields.named,
+                        part,
+                        kind,
+                        origin,
+                        "union",
+                    )?);
                 }
                 Use(_) => unreachable!(),
 
                 // Implementation items
                 ImplConst(_) => unreachable!(),
                 ImplFn(unwrapped_parent) => {
-                    match unwrapped_parent
+                    let items: Vec<_> = unwrapped_parent
                         .block
                         .stmts
                         .iter()
@@ -176,9 +192,8 @@ pub fn resolve_item<'lt>(
                                 None
                             }
                         })
-                        .filter_map(|i| SupportedItem::from_item(i, part, kind))
-                        .next()
-                    {
+                        .collect();
+                    match pick_item(&items, part, kind, import_source, origin)? {
                         Some(i) => parent = Some(i),
                         None => {
                             bail!(
@@ -186,6 +201,7 @@ pub fn resolve_item<'lt>(
                             )
                         }
                     }
+                    siblings = items;
                 }
                 ImplType(_) => unreachable!(),
 
@@ -196,7 +212,7 @@ pub fn resolve_item<'lt>(
                         bail!("Couldn't get item for {part:?} with kind {kind:?} because the parent trait function has no default");
                     };
 
-                    match block
+                    let items: Vec<_> = block
                         .stmts
                         .iter()
                         .filter_map(|s| {
@@ -206,9 +222,8 @@ pub fn resolve_item<'lt>(
                                 None
                             }
                         })
-                        .filter_map(|i| SupportedItem::from_item(i, part, kind))
-                        .next()
-                    {
+                        .collect();
+                    match pick_item(&items, part, kind, import_source, origin)? {
                         Some(i) => parent = Some(i),
                         None => {
                             bail!(
@@ -216,27 +231,173 @@ pub fn resolve_item<'lt>(
                             )
                         }
                     }
+                    siblings = items;
                 }
                 TraitType(_) => unreachable!(),
 
+                // Foreign items
+                ForeignFn(_) => unreachable!(),
+                ForeignStatic(_) => unreachable!(),
+                ForeignType(_) => unreachable!(),
+
                 // Struct/enum
-                Variant(_unwrapped_parent) => {
-                    bail!("Couldn't find a field with a name of {part:?} because the parent is an enum variant. Rustdoc doesn't seem to support enum variant field documentation. If they are classified as struct fields, please create a GitHub Issue");
+                Variant(unwrapped_parent) => {
+                    parent = Some(pick_field(
+                        &unwrapped_parent.fields,
+                        part,
+                        kind,
+                        origin,
+                        "enum variant",
+                    )?);
                 }
                 Field(_) => unreachable!(),
             }
         } else {
-            match candidates
-                .iter()
-                .filter_map(|i| SupportedItem::from_item(i, part, kind))
-                .next()
-            {
+            match pick_item(&siblings, part, kind, import_source, origin)? {
                 Some(i) => parent = Some(i),
                 None => bail!("Couldn't get item for {part:?} with kind {kind:?}"),
-            };
+            }
         }
     }
 
     let final_item = parent.unwrap(); // Should be safe as we will return an error if we can't find it
     Ok(final_item)
 }
+
+/// Finds the item named `part` among `items`. `use` items are also told apart by the path they import
+fn pick_item<'lt>(
+    items: &[&'lt Item],
+    part: &String,
+    kind: &ItemKind,
+    import_source: Option<&str>,
+    origin: &ItemOrigin,
+) -> Result<Option<SupportedItem<'lt>>, Box<dyn Error>> {
+    items
+        .iter()
+        .flat_map(|i| SupportedItem::from_item(i, part, kind, import_source))
+        .pick(part, origin)
+}
+
+/// Finds the impl block named `part` (see [`impl_name`]) of the type named `self_ident`.
+/// Impl blocks aren't inside the type they are for, so they are searched for in the items the type is in
+fn pick_impl<'lt>(
+    siblings: &[&'lt Item],
+    self_ident: &Ident,
+    part: &str,
+    origin: &ItemOrigin,
+) -> Result<SupportedItem<'lt>, Box<dyn Error>> {
+    match siblings
+        .iter()
+        .filter_map(|i| match i {
+            Item::Impl(item) => Some(item),
+            _ => None,
+        })
+        .filter(|item| impl_name(item) == part && impl_self_ident(item) == Some(self_ident))
+        .map(SupportedItem::Impl)
+        .pick(part, origin)?
+    {
+        Some(i) => Ok(i),
+        None => bail!("Couldn't find an impl block named {part:?} for {self_ident}"),
+    }
+}
+
+/// Finds the field named `part`. Fields of tuple structs and tuple variants don't have a name, so rustdoc names them after their position
+fn pick_field<'lt>(
+    fields: impl IntoIterator<Item = &'lt syn::Field>,
+    part: &str,
+    kind: &ItemKind,
+    origin: &ItemOrigin,
+    parent_kind: &str,
+) -> Result<SupportedItem<'lt>, Box<dyn Error>> {
+    if !matches!(kind, ItemKind::StructField) {
+        bail!("{part:?} was a {kind:?} but it should be a StructField since the parent was a {parent_kind}");
+    }
+
+    match fields
+        .into_iter()
+        .enumerate()
+        .filter(|(i, f)| match &f.ident {
+            Some(ident) => ident == part,
+            None => i.to_string() == part,
+        })
+        .map(|(_, f)| SupportedItem::Field(f))
+        .pick(part, origin)?
+    {
+        Some(f) => Ok(f),
+        None => bail!("Couldn't find a field with a name of {part:?} in the {parent_kind}"),
+    }
+}
+
+#[easy_ext::ext(CandidatesExt)]
+impl<'lt, I> I
+where
+    I: Iterator<Item = SupportedItem<'lt>>,
+{
+    /// Chooses between items with the same name and kind, which happens when items are duplicated with `#[cfg]`.
+    /// Prefers the item whose lines and `#[cfg]` attributes both match the origin, then only the attributes (the lines may be outdated),
+    /// then only the lines. If there is still more than one item left, we can't know which item is meant, so this fails instead of guessing.
+    fn pick(
+        self,
+        name: &str,
+        origin: &ItemOrigin,
+    ) -> Result<Option<SupportedItem<'lt>>, Box<dyn Error>> {
+        let mut candidates: Vec<_> = self.collect();
+        if candidates.len() <= 1 {
+            return Ok(candidates.pop());
+        }
+
+        let lines: Vec<_> = candidates
+            .iter()
+            .map(|c| {
+                let span = c.inner().span();
+                span.start().line..=span.end().line
+            })
+            .collect();
+        let lines_match: Vec<_> = lines
+            .iter()
+            .map(|lines| {
+                origin.lines.as_ref().is_some_and(|origin_lines| {
+                    lines.contains(origin_lines.start()) && lines.contains(origin_lines.end())
+                })
+            })
+            .collect();
+        let cfgs_match: Vec<_> = candidates
+            .iter()
+            .map(|c| {
+                let attrs: Vec<_> = c
+                    .attrs()
+                    .iter()
+                    .filter(|a| a.path().is_ident("cfg"))
+                    .filter_map(|a| a.meta.require_list().ok())
+                    .map(|list| format!("#[cfg({})]", list.tokens))
+                    .collect();
+                normalize_cfgs(attrs.iter().map(|a| a.as_str())) == origin.cfgs
+            })
+            .collect();
+        trace!(
+            name,
+            origin = debug(origin),
+            lines = debug(&lines),
+            lines_match = debug(&lines_match),
+            cfgs_match = debug(&cfgs_match),
+            "Choosing between items with the same path"
+        );
+
+        let both_match: Vec<_> = (0..candidates.len())
+            .map(|i| lines_match[i] && cfgs_match[i])
+            .collect();
+        for matches in [&both_match, &cfgs_match, &lines_match] {
+            if matches.iter().filter(|m| **m).count() == 1 {
+                let i = matches.iter().position(|m| *m).unwrap();
+                return Ok(Some(candidates.swap_remove(i)));
+            }
+        }
+
+        let lines = lines
+            .iter()
+            .map(|l| format!("{}-{}", l.start(), l.end()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        bail!("There are {} items named {name:?} (at lines {lines}) and their lines and #[cfg] attributes don't tell which one is meant. Please re-run to-markdown so the rustdoc JSON is up to date", candidates.len());
+    }
+}
@@ -1,30 +1,60 @@
+use std::ops::Range;
+
 use proc_macro2::{LineColumn, Span};
+use syn::spanned::Spanned;
+use syn::Attribute;
 use tracing::debug;
 
 use crate::doc_comment_parser::{get_doc_comment, DocCommentType};
 
+/// A change to a file that hasn't been applied yet. Edits are applied from the end of the file backwards so the byte ranges of the other edits stay valid
+#[derive(Debug)]
+pub struct DocsEdit {
+    pub range: Range<usize>,
+    pub docs: String,
+}
+
 #[tracing::instrument(skip(file_string))]
 pub fn update_docs_for_span(
     span: Span,
     new_docs: Vec<String>,
-    file_string: &mut String,
+    file_string: &str,
     default_comment_type: DocCommentType,
     add_extra_newline_for_new_comments: bool,
-) {
+) -> DocsEdit {
     let span_start_pos = span.start().byte_pos(file_string);
-    let span_source_text = span.source_text().unwrap();
+    // We can't use Span::source_text since proc_macro2 treats its character offsets as byte offsets when slicing the source text
+    let span_source_text = file_string[span_start_pos..span.end().byte_pos(file_string)].to_owned();
 
     match get_doc_comment(&span_source_text) {
         Some(doc_comment) => {
+            let mut range = (span_start_pos + doc_comment.start_index)
+                ..(span_start_pos + doc_comment.end_index);
+            if new_docs.is_empty() {
+                // Remove the lines of the doc comment entirely (including the indentation before it and the empty lines after it),
+                // so removing the docs doesn't leave empty lines behind
+                let line_start = file_string[..range.start].rfind('\n').map_or(0, |i| i + 1);
+                if file_string[line_start..range.start].trim().is_empty() {
+                    range.start = line_start;
+                }
+                let rest = &file_string[range.end..];
+                let whitespace = &rest[..rest.len() - rest.trim_start().len()];
+                range.end += whitespace.rfind('\n').map_or(0, |i| i + 1);
+                debug!(range = debug(&range), "Removing");
+                return DocsEdit {
+                    range,
+                    docs: String::new(),
+                };
+            }
+            let newline = line_ending(file_string);
+            let indentation = indentation_at(file_string, range.start);
             let docs = doc_comment
                 .comment_type
                 .edit_lines_for_comment_type(new_docs)
-                .join("\n");
-            let range = (span_start_pos + doc_comment.start_index)
-                ..(span_start_pos + doc_comment.end_index);
+                .join(&format!("{newline}{indentation}"));
 
             debug!(docs, range = debug(&range), "Replacing");
-            file_string.replace_range(range, &docs);
+            DocsEdit { range, docs }
         }
         None => {
             let LineColumn { line, column } = span.start();
@@ -35,7 +65,7 @@ pub fn update_docs_for_span(
                 file_string,
                 default_comment_type,
                 add_extra_newline_for_new_comments,
-            );
+            )
         }
     }
 }
@@ -44,36 +74,242 @@ pub fn update_docs_for_span(
 pub fn insert_new_doc_comment(
     index: usize,
     new_docs: Vec<String>,
-    file_string: &mut String,
+    file_string: &str,
     default_comment_type: DocCommentType,
     add_extra_newline_for_new_comments: bool,
-) {
+) -> DocsEdit {
+    if new_docs.is_empty() {
+        return DocsEdit {
+            range: index..index,
+            docs: String::new(),
+        };
+    }
+
+    // The doc comment is inserted where the item starts, which is after the indentation of the line.
+    // This means every line after the first one (including the line with the item) needs to be indented.
+    let newline = line_ending(file_string);
+    let indentation = indentation_at(file_string, index).to_owned();
     let mut docs = default_comment_type
         .edit_lines_for_comment_type(new_docs)
-        .join("\n");
-    docs.push_str("\n");
+        .join(&format!("{newline}{indentation}"));
+    docs.push_str(newline);
     if add_extra_newline_for_new_comments {
-        docs.push_str("\n");
+        docs.push_str(newline);
     }
+    docs.push_str(&indentation);
 
     debug!(docs, "Inserting");
-    file_string.insert_str(index, &docs);
+    DocsEdit {
+        range: index..index,
+        docs,
+    }
 }
 
-#[easy_ext::ext]
-impl LineColumn {
-    fn byte_pos(self, file: &String) -> usize {
-        let mut i = self.column;
-        if self.line > 1 {
-            let final_line = self.line - 1; // Make it 0 indexed
-            for (line_num, line) in file.split("\n").enumerate() {
-                if line_num >= final_line {
-                    break;
-                }
-                const NEWLINE_LENGTH: usize = "\n".len();
-                i += line.len() + NEWLINE_LENGTH;
+/// Removes the doc attributes (doc comments or `#[doc = ...]`) along with the lines they are on, so no empty lines are left behind.
+/// Each attribute is removed on its own, since other attributes can be between them. The empty lines after the last attribute are removed too
+#[tracing::instrument(skip(attrs, file_string))]
+pub fn remove_doc_attrs(attrs: &[&Attribute], file_string: &str) -> Vec<DocsEdit> {
+    let mut edits: Vec<DocsEdit> = vec![];
+    for attr in attrs {
+        let span = attr.span();
+        let mut range = span.start().byte_pos(file_string)..span.end().byte_pos(file_string);
+        let line_start = file_string[..range.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = file_string[range.end..]
+            .find('\n')
+            .map_or(file_string.len(), |i| range.end + i + 1);
+        let before = &file_string[line_start..range.start];
+        let after = &file_string[range.end..line_end];
+        match (before.trim().is_empty(), after.trim().is_empty()) {
+            // The attribute has its own lines
+            (true, true) => range = line_start..line_end,
+            // The attribute is at the end of a line, such as `mod foo { //! Docs`, so the line ending is kept
+            (false, true) => {
+                range.start = line_start + before.trim_end().len();
+                range.end = line_end - (after.len() - after.trim_end_matches(['\r', '\n']).len());
             }
+            // Something comes after the attribute on the same line
+            (_, false) => range.end += after.len() - after.trim_start().len(),
         }
-        i
+        edits.push(DocsEdit {
+            range,
+            docs: String::new(),
+        });
+    }
+
+    if let Some(last) = edits.last_mut() {
+        let rest = &file_string[last.range.end..];
+        let whitespace = &rest[..rest.len() - rest.trim_start().len()];
+        if file_string[..last.range.end].ends_with('\n') {
+            last.range.end += whitespace.rfind('\n').map_or(0, |i| i + 1);
+        }
+    }
+    debug!(edits = debug(&edits), "Removing");
+    edits
+}
+
+/// Gets the line ending used by the file so we don't mix line endings
+fn line_ending(file_string: &str) -> &'static str {
+    if file_string.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+/// Gets the whitespace (tabs or spaces) at the start of the line that contains `index`
+fn indentation_at(file_string: &str, index: usize) -> &str {
+    let line_start = file_string[..index].rfind('\n').map_or(0, |i| i + 1);
+    let line = &file_string[line_start..index];
+    let indentation_len = line.len() - line.trim_start_matches([' ', '\t']).len();
+    &line[..indentation_len]
+}
+
+#[easy_ext::ext(LineColumnExt)]
+pub impl LineColumn {
+    /// proc_macro2's column is a character offset, not a byte offset, so we have to find the byte offset of the character ourselves
+    fn byte_pos(self, file: &str) -> usize {
+        let line_start: usize = file
+            .split_inclusive('\n') // Keeps the newline (including `\r`) so it is counted in the length
+            .take(self.line - 1) // Lines are 1 indexed
+            .map(str::len)
+            .sum();
+        let mut line = &file[line_start..];
+        let mut line_start = line_start;
+        if line_start == 0 && line.starts_with(BOM) {
+            // syn removes the byte order mark before parsing, so it isn't included in the column
+            line = &line[BOM.len()..];
+            line_start += BOM.len();
+        }
+        line_start
+            + line
+                .char_indices()
+                .nth(self.column)
+                .map_or(line.len(), |(i, _)| i)
+    }
+}
+
+const BOM: &str = "\u{feff}";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The span of the docs of the first field of the first struct in the file, like `update_docs` gets it
+    fn field_docs_span(file: &str) -> Span {
+        let syn::Item::Struct(item) = &syn::parse_file(file).unwrap().items[0] else {
+            panic!("the first item should be a struct");
+        };
+        let field = item.fields.iter().next().unwrap();
+        field
+            .attrs
+            .iter()
+            .map(|a| a.span())
+            .reduce(|span, attr_span| span.join(attr_span).unwrap())
+            .unwrap()
+    }
+
+    fn apply(file: &str, edit: DocsEdit) -> String {
+        let mut file = file.to_owned();
+        file.replace_range(edit.range, &edit.docs);
+        file
+    }
+
+    fn replace_field_docs(file: &str, new_docs: &[&str]) -> String {
+        let new_docs = new_docs.iter().map(|l| l.to_string()).collect();
+        let edit = update_docs_for_span(
+            field_docs_span(file),
+            new_docs,
+            file,
+            DocCommentType::OuterSingle,
+            false,
+        );
+        apply(file, edit)
+    }
+
+    /// The source text of the last item in the file, found with the byte offsets of its span
+    fn last_item_text(file: &str) -> &str {
+        let item = syn::parse_file(file).unwrap().items.pop().unwrap();
+        let span = item.span();
+        &file[span.start().byte_pos(file)..span.end().byte_pos(file)]
+    }
+
+    #[test]
+    fn byte_pos_of_ascii() {
+        let file = "fn a() {}\nfn b() {}\n";
+        assert_eq!(LineColumn { line: 1, column: 0 }.byte_pos(file), 0);
+        assert_eq!(LineColumn { line: 2, column: 3 }.byte_pos(file), 13);
+        // The end of the line and the end of the file
+        assert_eq!(LineColumn { line: 2, column: 9 }.byte_pos(file), 19);
+        assert_eq!(LineColumn { line: 3, column: 0 }.byte_pos(file), 20);
+    }
+
+    #[test]
+    fn byte_pos_of_multi_byte_characters() {
+        // `é` is 2 bytes and `🦀` is 4 bytes, but each is 1 column
+        let file = "/// é🦀\nconst S: &str = \"é🦀\"; fn a() {}\n";
+        assert_eq!(LineColumn { line: 1, column: 5 }.byte_pos(file), 4 + 2);
+        assert_eq!(LineColumn { line: 1, column: 6 }.byte_pos(file), 4 + 2 + 4);
+        assert_eq!(
+            LineColumn {
+                line: 2,
+                column: 22
+            }
+            .byte_pos(file),
+            11 + 17 + 2 + 4 + 3
+        );
+        assert_eq!(last_item_text(file), "fn a() {}");
+    }
+
+    #[test]
+    fn byte_pos_with_crlf_line_endings() {
+        let file = "/// é\r\nfn a() {}\r\n/// 🦀\r\nfn b() {}\r\n";
+        assert_eq!(LineColumn { line: 2, column: 0 }.byte_pos(file), 8);
+        assert_eq!(
+            LineColumn { line: 4, column: 3 }.byte_pos(file),
+            8 + 11 + 10 + 3
+        );
+        assert_eq!(last_item_text(file), "/// 🦀\r\nfn b() {}");
+    }
+
+    #[test]
+    fn byte_pos_with_byte_order_mark() {
+        let file = "\u{feff}fn a() {}\nfn b() {}\n";
+        // syn ignores the byte order mark, so the first column is after it
+        assert_eq!(LineColumn { line: 1, column: 0 }.byte_pos(file), 3);
+        assert_eq!(LineColumn { line: 1, column: 3 }.byte_pos(file), 6);
+        assert_eq!(LineColumn { line: 2, column: 0 }.byte_pos(file), 13);
+        let item = syn::parse_file(file).unwrap().items.remove(0);
+        let span = item.span();
+        assert_eq!(
+            &file[span.start().byte_pos(file)..span.end().byte_pos(file)],
+            "fn a() {}"
+        );
+    }
+
+    #[test]
+    fn replaces_docs_with_crlf_line_endings() {
+        let file = "struct S {\r\n    /// é\r\n    /// 🦀\r\n    f: u8,\r\n}\r\n";
+        assert_eq!(
+            replace_field_docs(file, &["New", "Docs"]),
+            "struct S {\r\n    /// New\r\n    /// Docs\r\n    f: u8,\r\n}\r\n"
+        );
+    }
+
+    #[test]
+    fn replaces_indented_multi_line_docs() {
+        let file = "struct S {\n    /// One\n    /// Two\n    /// Three\n    f: u8,\n}\n";
+        assert_eq!(
+            replace_field_docs(file, &["New", "", "Docs"]),
+            "struct S {\n    /// New\n    ///\n    /// Docs\n    f: u8,\n}\n"
+        );
+    }
+
+    #[test]
+    fn replaces_tab_indented_multi_line_docs() {
+        let file = "struct S {\n\t/// One\n\t/// Two\n\tf: u8,\n}\n";
+        assert_eq!(
+            replace_field_docs(file, &["New"]),
+            "struct S {\n\t/// New\n\tf: u8,\n}\n"
+        );
     }
 }
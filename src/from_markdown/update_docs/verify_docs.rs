@@ -0,0 +1,144 @@
+use std::error::Error;
+
+use cli_failure::bail;
+use syn::{AttrStyle, Attribute, Expr, ExprLit, Lit, Meta};
+use tracing::debug;
+
+use crate::from_markdown::item_info::ItemInfo;
+
+use super::resolve_item::resolve_item;
+
+/// Checks that the item in the updated file has the new docs. `syn_file` should be the updated file parsed with syn.
+#[wrap_match::wrap_match(log_success = false)]
+#[tracing::instrument(skip(syn_file))]
+pub fn verify_docs(syn_file: &syn::File, item: &ItemInfo) -> Result<(), Box<dyn Error>> {
+    let attrs: Vec<_> = if item.item_path.is_empty() {
+        // The item is the file itself, so only the inner attributes are its docs
+        syn_file
+            .attrs
+            .iter()
+            .filter(|a| matches!(a.style, AttrStyle::Inner(_)))
+            .cloned()
+            .collect()
+    } else {
+        resolve_item(&syn_file.items, &item.item_path)?
+            .attrs()
+            .to_vec()
+    };
+
+    let Some(docs) = docs_from_attrs(&attrs) else {
+        bail!("The item has a doc attribute that isn't a string literal, so its docs can't be verified");
+    };
+    let docs = normalize_docs(docs);
+    let expected_docs = normalize_docs(item.new_docs.clone());
+    debug!(
+        docs = docs.join("\n"),
+        expected_docs = expected_docs.join("\n")
+    );
+    if docs != expected_docs {
+        bail!("The docs of the item are different from the new docs after updating the file");
+    }
+
+    Ok(())
+}
+
+/// Gets the lines of all the `#[doc = "..."]` attributes (which doc comments are desugared into).
+/// Returns `None` if a doc attribute isn't a string literal, for example `#[doc = include_str!("...")]`.
+pub fn docs_from_attrs(attrs: &[Attribute]) -> Option<Vec<String>> {
+    let mut lines = vec![];
+    for attr in attrs.iter().filter(|a| a.path().is_ident("doc")) {
+        let Meta::NameValue(meta) = &attr.meta else {
+            // `#[doc(hidden)]` and similar don't contain docs
+            continue;
+        };
+        let Expr::Lit(ExprLit {
+            lit: Lit::Str(lit), ..
+        }) = &meta.value
+        else {
+            return None;
+        };
+        // `lines` would skip the empty lines of `///` comments without text, since their value is an empty string
+        lines.extend(
+            lit.value()
+                .split('\n')
+                .map(|l| l.trim_end_matches('\r').to_owned()),
+        );
+    }
+    Some(lines)
+}
+
+/// Removes the indentation shared by all lines (like rustdoc does), trailing whitespace and leading/trailing empty lines
+fn normalize_docs(lines: Vec<String>) -> Vec<String> {
+    let indentation = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or_default();
+    let lines: Vec<_> = lines
+        .iter()
+        .map(|l| {
+            l.get(indentation..)
+                .unwrap_or_default()
+                .trim_end()
+                .to_owned()
+        })
+        .collect();
+
+    let Some(start) = lines.iter().position(|l| !l.is_empty()) else {
+        return vec![];
+    };
+    let end = lines.iter().rposition(|l| !l.is_empty()).unwrap() + 1;
+    lines[start..end].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn removes_trailing_whitespace() {
+        assert_eq!(
+            normalize_docs(lines(&["One  ", "Two\t", "Three \r"])),
+            lines(&["One", "Two", "Three"])
+        );
+    }
+
+    #[test]
+    fn removes_leading_and_trailing_blank_lines() {
+        assert_eq!(
+            normalize_docs(lines(&["", "  ", "One", "", "", "Two", " ", ""])),
+            lines(&["One", "", "", "Two"])
+        );
+        assert_eq!(
+            normalize_docs(lines(&["", " ", "\t"])),
+            Vec::<String>::new()
+        );
+        assert_eq!(normalize_docs(vec![]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn removes_shared_indentation() {
+        // Blank lines don't count towards the shared indentation, and the rest of the indentation is kept
+        assert_eq!(
+            normalize_docs(lines(&[" One", "", "     Code", "  "])),
+            lines(&["One", "", "    Code"])
+        );
+    }
+
+    #[test]
+    fn is_idempotent() {
+        for docs in [
+            lines(&["  One  ", "", "    Two", "", ""]),
+            lines(&["", "\tTabs", "\t\tMore tabs\t"]),
+            lines(&["No changes", "", "- list"]),
+        ] {
+            let normalized = normalize_docs(docs);
+            assert_eq!(normalize_docs(normalized.clone()), normalized);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Snapshots of the source files modified by from-markdown, so that the last run can be undone with `doc-sync undo`.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use cli_failure::bail;
+use tracing::{debug, info};
+use xshell::Shell;
+
+use crate::write_files::PendingFile;
+
+pub const JOURNAL_DIR: &str = "./target/doc-sync-journal";
+/// Lists the snapshotted files. It is written last, so a journal without it is incomplete and ignored
+const MANIFEST_FILE: &str = "manifest";
+/// Contains the input directory of the run, so undo can tell to-markdown that the markdown files haven't been converted back anymore
+const INPUT_DIR_FILE: &str = "input_dir";
+const ORIGINAL_SUFFIX: &str = ".original";
+const MODIFIED_SUFFIX: &str = ".modified";
+
+pub struct JournalEntry {
+    pub file_path: String,
+    pub original_contents: String,
+    pub modified_contents: String,
+}
+
+/// Replaces the previous journal with snapshots of the files that are about to be written
+#[wrap_match::wrap_match(log_success = false)]
+#[tracing::instrument(skip(sh, files))]
+pub fn write_journal(
+    sh: &Shell,
+    input_dir: &Path,
+    files: &BTreeMap<String, PendingFile>,
+) -> Result<(), Box<dyn Error>> {
+    let journal_dir = PathBuf::from(JOURNAL_DIR);
+    if sh.path_exists(&journal_dir) {
+        debug!("Removing previous journal");
+        sh.remove_path(&journal_dir)?;
+    }
+
+    info!("Snapshotting {} files to {JOURNAL_DIR}", files.len());
+    let mut manifest = String::new();
+    for (i, (file_path, file)) in files.iter().enumerate() {
+        sh.write_file(
+            journal_dir.join(format!("{i}{ORIGINAL_SUFFIX}")),
+            &file.original_contents,
+        )?;
+        sh.write_file(
+            journal_dir.join(format!("{i}{MODIFIED_SUFFIX}")),
+            &file.new_contents,
+        )?;
+        manifest.push_str(&format!("{i}\t{file_path}\n"));
+    }
+    sh.write_file(
+        journal_dir.join(INPUT_DIR_FILE),
+        input_dir.display().to_string(),
+    )?;
+    sh.write_file(journal_dir.join(MANIFEST_FILE), manifest)?;
+
+    Ok(())
+}
+
+/// The input directory of a run and the files it modified
+pub type Journal = (PathBuf, Vec<JournalEntry>);
+
+/// Updates the snapshots of the modified files to their current contents. Used after formatting the files,
+/// since undo refuses to restore files that are different from their snapshot
+#[wrap_match::wrap_match(log_success = false)]
+pub fn update_modified_snapshots(sh: &Shell) -> Result<(), Box<dyn Error>> {
+    let journal_dir = PathBuf::from(JOURNAL_DIR);
+    if !sh.path_exists(journal_dir.join(MANIFEST_FILE)) {
+        return Ok(());
+    }
+
+    debug!("Updating the snapshots of the modified files");
+    for line in sh.read_file(journal_dir.join(MANIFEST_FILE))?.lines() {
+        let Some((i, file_path)) = line.split_once('\t') else {
+            bail!("Invalid line in the journal manifest: {line:?}");
+        };
+        sh.write_file(
+            journal_dir.join(format!("{i}{MODIFIED_SUFFIX}")),
+            sh.read_file(file_path)?,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Returns the input directory of the last run and the snapshotted files, or `None` if there is no complete journal
+#[wrap_match::wrap_match(log_success = false)]
+pub fn read_journal(sh: &Shell) -> Result<Option<Journal>, Box<dyn Error>> {
+    let journal_dir = PathBuf::from(JOURNAL_DIR);
+    if !sh.path_exists(journal_dir.join(MANIFEST_FILE)) {
+        return Ok(None);
+    }
+
+    let input_dir = PathBuf::from(sh.read_file(journal_dir.join(INPUT_DIR_FILE))?);
+    let mut entries = vec![];
+    for line in sh.read_file(journal_dir.join(MANIFEST_FILE))?.lines() {
+        let Some((i, file_path)) = line.split_once('\t') else {
+            bail!("Invalid line in the journal manifest: {line:?}");
+        };
+        entries.push(JournalEntry {
+            file_path: file_path.to_owned(),
+            original_contents: sh.read_file(journal_dir.join(format!("{i}{ORIGINAL_SUFFIX}")))?,
+            modified_contents: sh.read_file(journal_dir.join(format!("{i}{MODIFIED_SUFFIX}")))?,
+        });
+    }
+
+    Ok(Some((input_dir, entries)))
+}
+
+#[wrap_match::wrap_match(log_success = false)]
+pub fn remove_journal(sh: &Shell) -> Result<(), Box<dyn Error>> {
+    sh.remove_path(JOURNAL_DIR)?;
+    Ok(())
+}
@@ -1,13 +1,20 @@
-use std::{error::Error, path::PathBuf, str::FromStr};
+use std::{error::Error, path::PathBuf, process::ExitCode, str::FromStr};
 
 use clap::{Parser, Subcommand};
+use from_markdown::{Formatter, MessageFormat, ModuleDocStyle};
+use to_markdown::{Layout, TargetKind};
 use toml::Table;
 use xshell::Shell;
 
 mod doc_comment_parser;
 mod edit_docs;
 mod from_markdown;
+mod journal;
+mod parallel;
+mod rustdoc_json;
 mod to_markdown;
+mod undo;
+mod write_files;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -20,6 +27,7 @@ struct Cli {
 enum CliCommand {
     ToMarkdown(ToMarkdown),
     FromMarkdown(FromMarkdown),
+    Undo(Undo),
 }
 
 #[derive(Parser)]
@@ -42,6 +50,25 @@ pub struct ToMarkdown {
     #[arg(short, long, default_value_t = false)]
     /// If true, doc-sync will overwrite generated markdown files even if they haven't been converted back to doc comments.
     force: bool,
+    #[arg(long, num_args = 1..)]
+    /// Previously generated rustdoc JSON files to use instead of running cargo doc. Pass `-` to read the JSON from stdin.
+    /// Each crate will be put in its own directory in the output directory.
+    rustdoc_json: Option<Vec<PathBuf>>,
+    #[arg(long, num_args = 1..)]
+    /// Feature sets to run cargo doc with, each as a comma-separated list of features (an empty string uses only the default features).
+    /// The items of every feature set are merged, so feature-gated items get exported too.
+    feature_sets: Option<Vec<String>>,
+    #[arg(long, value_enum, num_args = 1..)]
+    /// The kinds of targets to export, each in its own directory in the output directory.
+    /// By default, only the target named after the package is exported.
+    target_kinds: Option<Vec<TargetKind>>,
+    #[arg(long, default_value_t = false)]
+    /// If true, doc-sync will always run cargo doc, even if the sources, toolchain and arguments haven't changed since the rustdoc JSON was last generated.
+    no_cache: bool,
+    #[arg(long, value_enum, default_value_t = Layout::Plain)]
+    /// The layout of the output directory. The site layouts add a table of contents mirroring the module hierarchy,
+    /// so the output directory can be published as a site. The markdown files can still be converted back with from-markdown.
+    layout: Layout,
 }
 
 #[derive(Parser)]
@@ -51,23 +78,64 @@ pub struct FromMarkdown {
     /// The directory to use as input. This should be the same as the directory used when generating the markdown files.
     input_dir: PathBuf,
     #[arg(short, long, default_value_t = false)]
-    /// If true, doc-sync will not exit if there are uncommitted changes.
+    /// If true, doc-sync will not exit if the files it is about to modify have uncommitted changes.
     allow_dirty: bool,
+    #[arg(long, value_enum, default_value_t = Formatter::None)]
+    /// How the files modified by doc-sync should be formatted after updating the doc comments.
+    formatter: Formatter,
+    #[arg(long, required_if_eq("formatter", "command"))]
+    /// The command to run when `--formatter command` is passed. `{files}` will be replaced with the modified files.
+    /// If `{files}` isn't in the command, the modified files will be appended to the end of it.
+    format_command: Option<String>,
+    #[arg(long, value_enum, default_value_t = ModuleDocStyle::Outer)]
+    /// Where new docs of inline modules go. Existing docs are always updated where they are.
+    module_doc_style: ModuleDocStyle,
+    #[arg(long, num_args = 1..)]
+    /// The rustdoc JSON files the markdown files were generated from. Pass `-` to read the JSON from stdin.
+    /// By default, the JSON stored in the input directory by to-markdown is used.
+    rustdoc_json: Option<Vec<PathBuf>>,
+    #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+    /// The format of the report printed at the end.
+    message_format: MessageFormat,
 }
 
-fn main() {
+#[derive(Parser)]
+/// Restores the source files modified by the last run of from-markdown.
+pub struct Undo {
+    #[arg(short, long, default_value_t = false)]
+    /// If true, doc-sync will restore the files even if they have been changed since from-markdown modified them.
+    force: bool,
+}
+
+/// The exit code used when at least one markdown file couldn't be converted back
+pub const EXIT_ITEMS_FAILED: u8 = 1;
+/// The exit code used when doc-sync couldn't finish running
+pub const EXIT_ERROR: u8 = 2;
+/// The exit code used when the docs were written, but the modified files couldn't be formatted
+pub const EXIT_FORMAT_FAILED: u8 = 3;
+
+fn main() -> ExitCode {
     let args = Cli::parse();
 
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "trace")
     }
-    tracing_subscriber::fmt::init();
+    // Logs go to stderr so stdout can be used for output such as the from-markdown report
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
 
     let sh = Shell::new().unwrap();
 
-    match args.command {
+    let result = match args.command {
         CliCommand::ToMarkdown(args) => to_markdown::to_markdown(sh, args),
-        CliCommand::FromMarkdown(args) => from_markdown::from_markdown(sh, args),
+        CliCommand::FromMarkdown(args) => return from_markdown::from_markdown(sh, args),
+        CliCommand::Undo(args) => undo::undo(sh, args),
+    };
+    // The error has already been logged
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(_) => ExitCode::from(EXIT_ERROR),
     }
 }
 
@@ -77,6 +145,20 @@ mod consts {
     pub const METADATA_COMMENT_SUFFIX: &str = " -->";
     pub const METADATA_ID_PREFIX: &str = "DOC_SYNC_RUSTDOC_ID=\"";
     pub const METADATA_ID_SUFFIX: &str = "\"";
+    pub const METADATA_FEATURE_SETS_PREFIX: &str = "DOC_SYNC_FEATURE_SETS=\"";
+    pub const METADATA_FEATURE_SETS_SUFFIX: &str = "\"";
+    pub const METADATA_READ_ONLY_PREFIX: &str = "DOC_SYNC_READ_ONLY=\"";
+    pub const METADATA_READ_ONLY_SUFFIX: &str = "\"";
+    /// The header above the docs of each markdown file is between these lines. from-markdown removes it, so edits to it are ignored
+    pub const HEADER_START: &str = "<!-- doc-sync: the header below is generated and is removed by from-markdown, so edits to it are ignored -->";
+    pub const HEADER_END: &str = "<!-- doc-sync: end of header -->";
+    /// Every directory of the output directory has an index file listing the items in it
+    pub const INDEX_FILE_NAME: &str = "_index.md";
+    /// The table of contents of the mdBook layout, which from-markdown ignores like the index files
+    pub const SUMMARY_FILE_NAME: &str = "SUMMARY.md";
+    pub const INDEX_FILE_HEADER: &str = "<!-- doc-sync: this file is generated by to-markdown and ignored by from-markdown, so edits to it are lost -->";
+    /// Separates the outer docs of a `mod foo;` declaration from the inner docs of the module file in the markdown file of the module
+    pub const MODULE_DOCS_SEPARATOR: &str = "<!-- doc-sync: the docs above this line are on the `mod` declaration and the docs below it are at the top of the module file. Remove this line to move all the docs into the module file -->";
 }
 
 #[wrap_match::wrap_match(log_success = false)]
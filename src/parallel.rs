@@ -0,0 +1,39 @@
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::thread;
+
+/// Maps every input using all available threads. The outputs are in the same order as the inputs
+pub fn parallel_map<T: Send, R: Send>(inputs: Vec<T>, f: impl Fn(T) -> R + Sync) -> Vec<R> {
+    let len = inputs.len();
+    let threads = thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(len);
+    if threads <= 1 {
+        return inputs.into_iter().map(f).collect();
+    }
+
+    // Threads take the next input when they are done, so one slow input doesn't hold back the inputs after it
+    let queue = Mutex::new(inputs.into_iter().enumerate());
+    let mut outputs: Vec<(usize, R)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut outputs = vec![];
+                    loop {
+                        let Some((i, input)) = queue.lock().unwrap().next() else {
+                            break;
+                        };
+                        outputs.push((i, f(input)));
+                    }
+                    outputs
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("worker thread panicked"))
+            .collect()
+    });
+    outputs.sort_by_key(|(i, _)| *i);
+    outputs.into_iter().map(|(_, output)| output).collect()
+}
@@ -0,0 +1,53 @@
+//! Crate docs
+
+/// A struct
+#[must_use]
+#[non_exhaustive]
+pub struct Point {
+    /// A field
+    pub x: u8,
+}
+
+/// A function
+pub fn function<const N: usize>(_: [u8; N], point: &mut Point) -> impl Sized + use<N> {
+    point.x
+}
+
+/// Only with feature a
+#[cfg(feature = "a")]
+pub fn with_a() {}
+
+/// Only without feature a
+#[cfg(not(any(feature = "a", test)))]
+pub fn without_a() {}
+
+/// A type alias
+pub type Alias = Point;
+
+/// A re-export
+pub use inner::inner as renamed;
+
+pub mod inner {
+    /// An inner function
+    pub fn inner() {}
+}
+
+/// A constant
+pub const CONSTANT: u8 = 1;
+
+/// A trait
+pub trait Trait {
+    /// An associated constant
+    const ASSOC: u8 = 2;
+    /// An associated type
+    type Assoc: Iterator<Item = u8>;
+}
+
+impl Trait for Point {
+    type Assoc = std::iter::Empty<u8>;
+}
+
+extern "C" {
+    /// A foreign function
+    pub fn foreign();
+}
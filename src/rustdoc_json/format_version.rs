@@ -0,0 +1,469 @@
+//! doc-sync works with the types of rustdoc JSON format version 26 (from `rustdoc-types` 0.22).
+//! JSON in format version 57 is converted to format version 26 before it is deserialized, so doc-sync also works with a recent nightly.
+//! Only the shape of the JSON is converted; information that doesn't exist in format version 26 is dropped.
+//! The format versions in between aren't accepted, since the conversion has only been checked against real output of format version 57.
+
+use std::error::Error;
+use std::sync::LazyLock;
+
+use cli_failure::bail;
+use regex::Regex;
+use rustdoc_types::{Crate, FORMAT_VERSION};
+use serde_json::{json, Map, Value};
+use tracing::{debug, info};
+
+/// The format version that is converted to [`FORMAT_VERSION`], the format version of the types doc-sync uses internally
+const CONVERTED_FORMAT_VERSION: u32 = 57;
+/// A nightly that produces [`CONVERTED_FORMAT_VERSION`], for error messages
+const CONVERTED_FORMAT_NIGHTLY: &str = "nightly-2026-05-19";
+
+/// Keys that contain numbers in format version 26. Every other number is an ID, which became an integer in newer format versions
+const NUMERIC_KEYS: &[&str] = &["crate_id", "format_version", "begin", "end"];
+
+/// Fields that were renamed after format version 26, as `(new name, old name)`
+const RENAMED_FIELDS: &[(&str, &str)] = &[
+    ("is_mutable", "mutable"),
+    ("is_synthetic", "synthetic"),
+    ("is_negative", "negative"),
+    ("is_glob", "glob"),
+    ("is_c_variadic", "c_variadic"),
+    ("has_stripped_fields", "fields_stripped"),
+    ("has_stripped_variants", "variants_stripped"),
+    ("sig", "decl"),
+];
+
+/// Item kinds that were renamed after format version 26, as `(new name, old name)`. Used for `ItemEnum` and `ItemKind`
+const RENAMED_KINDS: &[(&str, &str)] = &[
+    ("use", "import"),
+    ("type_alias", "typedef"),
+    ("extern_type", "foreign_type"),
+];
+
+/// The item kinds that exist in format version 26. Items of other kinds can't be represented, so they are dropped
+const KNOWN_KINDS: &[&str] = &[
+    "module",
+    "extern_crate",
+    "import",
+    "struct",
+    "struct_field",
+    "union",
+    "enum",
+    "variant",
+    "function",
+    "typedef",
+    "opaque_ty",
+    "constant",
+    "trait",
+    "trait_alias",
+    "impl",
+    "static",
+    "foreign_type",
+    "macro",
+    "proc_attribute",
+    "proc_derive",
+    "proc_macro",
+    "assoc_const",
+    "assoc_type",
+    "primitive",
+    "keyword",
+];
+
+/// Checks the format version of the JSON, converts it to format version 26 if needed and deserializes it
+#[wrap_match::wrap_match(log_success = false)]
+pub fn deserialize_rustdoc_json(json: &str) -> Result<Crate, Box<dyn Error>> {
+    let mut json: Value = serde_json::from_str(json)?;
+
+    let Some(format_version) = json.get("format_version").and_then(Value::as_u64) else {
+        bail!("The JSON doesn't have a format version, is it rustdoc JSON output?");
+    };
+    debug!(format_version);
+    if format_version == CONVERTED_FORMAT_VERSION as u64 {
+        info!("Converting rustdoc JSON format version {format_version} to {FORMAT_VERSION}");
+        convert_value(&mut json, None);
+        remove_unknown_kinds(&mut json);
+        json["format_version"] = FORMAT_VERSION.into();
+    } else if format_version != FORMAT_VERSION as u64 {
+        bail!("rustdoc JSON format version {format_version} is not supported. doc-sync supports format versions {FORMAT_VERSION} and {CONVERTED_FORMAT_VERSION}. Please use a nightly toolchain that produces one of them (for example with `--cargo-arguments +{CONVERTED_FORMAT_NIGHTLY}`).");
+    }
+
+    let krate = serde_json::from_value(json)?;
+    Ok(krate)
+}
+
+/// Recursively converts the JSON to format version 26. `key` is the key of the value in its parent object (array elements get the key of the array)
+fn convert_value(value: &mut Value, key: Option<&str>) {
+    match value {
+        Value::Number(number) => {
+            if !key.is_some_and(|k| NUMERIC_KEYS.contains(&k)) {
+                *value = Value::String(number.to_string());
+            }
+        }
+        Value::String(string) => match key {
+            // Unit variants of `ItemEnum` and `ItemKind`
+            Some("inner" | "kind") => {
+                if let Some((_, old)) = RENAMED_KINDS.iter().find(|(new, _)| new == string) {
+                    *string = (*old).to_owned();
+                }
+            }
+            // `GenericArgs::ReturnTypeNotation` doesn't exist in format version 26
+            Some("args") if string == "return_type_notation" => *value = empty_generic_args(),
+            _ => {}
+        },
+        Value::Array(array) => {
+            for element in array.iter_mut() {
+                convert_value(element, key);
+            }
+            match key {
+                // `GenericBound::Use` (precise capturing) doesn't exist in format version 26
+                Some("bounds" | "impl_trait") => {
+                    array.retain(|b| !b.as_object().is_some_and(|b| b.contains_key("use")))
+                }
+                // Attributes became structured, but format version 26 only has strings
+                Some("attrs") => {
+                    *array = array
+                        .iter()
+                        .flat_map(attribute_to_strings)
+                        .map(Value::String)
+                        .collect();
+                }
+                _ => {}
+            }
+        }
+        Value::Object(object) => {
+            if key == Some("attrs") {
+                // Converted to a string by the parent array
+                return;
+            }
+            for (key, value) in object.iter_mut() {
+                convert_value(value, Some(key));
+            }
+            if let Some(replacement) = convert_object(object, key) {
+                *value = replacement;
+            }
+        }
+        Value::Null | Value::Bool(_) => {}
+    }
+}
+
+/// Converts an object whose children have already been converted. Returns a value if the object should be replaced
+fn convert_object(object: &mut Map<String, Value>, key: Option<&str>) -> Option<Value> {
+    for (new, old) in RENAMED_FIELDS {
+        if let Some(value) = object.remove(*new) {
+            object.insert((*old).to_owned(), value);
+        }
+    }
+
+    // `Header`
+    if object.contains_key("abi") {
+        for (new, old) in [
+            ("is_const", "const"),
+            ("is_unsafe", "unsafe"),
+            ("is_async", "async"),
+        ] {
+            if let Some(value) = object.remove(new) {
+                object.insert(old.to_owned(), value);
+            }
+        }
+    }
+
+    // `Path`
+    if object.len() == 3
+        && object.contains_key("path")
+        && object.contains_key("id")
+        && object.contains_key("args")
+    {
+        let path = object.remove("path").unwrap();
+        object.insert("name".to_owned(), path);
+    }
+
+    // `GenericArgs::AngleBracketed`
+    if let Some(mut constraints) = object.remove("constraints") {
+        for constraint in constraints.as_array_mut().into_iter().flatten() {
+            if constraint.get("args").is_some_and(Value::is_null) {
+                constraint["args"] = empty_generic_args();
+            }
+        }
+        object.insert("bindings".to_owned(), constraints);
+    }
+
+    // `Type::QualifiedPath`
+    if object.contains_key("self_type") && object.get("args").is_some_and(Value::is_null) {
+        object.insert("args".to_owned(), empty_generic_args());
+    }
+
+    // `Type::Pat` doesn't exist in format version 26, so we use the type it restricts
+    if let Some(pat) = object.get("pat") {
+        if object.len() == 1 {
+            if let Some(type_) = pat.get("type") {
+                return Some(type_.clone());
+            }
+        }
+    }
+
+    // `GenericArg::Const` and `Term::Constant` no longer contain the type of the constant
+    for variant in ["const", "constant"] {
+        if let Some(Value::Object(constant)) = object.get_mut(variant) {
+            if constant.contains_key("expr") && !constant.contains_key("type") {
+                constant.insert("type".to_owned(), json!("infer"));
+            }
+        }
+    }
+
+    if key == Some("inner") {
+        for (new, old) in RENAMED_KINDS {
+            if let Some(value) = object.remove(*new) {
+                object.insert((*old).to_owned(), value);
+            }
+        }
+
+        // The constant item's expression was moved into a separate object
+        if let Some(Value::Object(constant)) = object.get_mut("constant") {
+            if let Some(Value::Object(const_)) = constant.remove("const") {
+                constant.extend(const_);
+            }
+        }
+        if let Some(Value::Object(assoc_const)) = object.get_mut("assoc_const") {
+            if let Some(value) = assoc_const.remove("value") {
+                assoc_const.insert("default".to_owned(), value);
+            }
+        }
+        if let Some(Value::Object(assoc_type)) = object.get_mut("assoc_type") {
+            if let Some(type_) = assoc_type.remove("type") {
+                assoc_type.insert("default".to_owned(), type_);
+            }
+        }
+    }
+
+    None
+}
+
+fn empty_generic_args() -> Value {
+    json!({ "angle_bracketed": { "args": [], "bindings": [] } })
+}
+
+/// Newer format versions have structured attributes, for example `{"must_use": {"reason": null}}` or `"non_exhaustive"`.
+/// Attributes that rustdoc couldn't structure are `{"other": "#[...]"}`.
+fn attribute_to_strings(attr: &Value) -> Vec<String> {
+    match attr {
+        Value::String(attr) => {
+            if attr.starts_with("#[") {
+                vec![attr.to_owned()]
+            } else {
+                vec![format!("#[{attr}]")]
+            }
+        }
+        Value::Object(attr) => match attr.iter().next() {
+            Some((name, Value::String(other))) if name == "other" => {
+                cfg_trace_to_cfgs(other).unwrap_or_else(|| vec![other.to_owned()])
+            }
+            Some((name, _)) => vec![format!("#[{name}]")],
+            None => vec![],
+        },
+        _ => vec![attr.to_string()],
+    }
+}
+
+static CFG_TRACE_SPAN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r", (?:span: )?[^\s,]+:\d+:\d+: \d+:\d+ \(#\d+\)").unwrap());
+static CFG_TRACE_NAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"NameValue \{ name: "([^"]*)", value: None \}"#).unwrap());
+static CFG_TRACE_NAME_VALUE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"NameValue \{ name: "([^"]*)", value: Some\(("(?:[^"\\]|\\.)*")\) \}"#).unwrap()
+});
+
+/// `#[cfg]` attributes are only available as the debug representation of rustc's `CfgTrace`, for example
+/// `#[attr = CfgTrace([Not(NameValue { name: "feature", value: Some("a"), span: src/lib.rs:1:11: 1:24 (#0) }, src/lib.rs:1:7: 1:25 (#0))])]`.
+/// This converts them back to `#[cfg(...)]` attributes (`#[cfg(not(feature = "a"))]`), so they can be compared with the attributes in the source.
+/// Returns `None` if the attribute isn't a `CfgTrace` or contains something we don't know how to convert.
+fn cfg_trace_to_cfgs(attr: &str) -> Option<Vec<String>> {
+    let trace = attr
+        .strip_prefix("#[attr = CfgTrace([")?
+        .strip_suffix("])]")?;
+
+    let trace = CFG_TRACE_SPAN.replace_all(trace, "");
+    let trace = CFG_TRACE_NAME.replace_all(&trace, "$1");
+    let trace = CFG_TRACE_NAME_VALUE.replace_all(&trace, "$1 = $2");
+    let trace = trace
+        .replace("Not(", "not(")
+        .replace("All([", "all(")
+        .replace("Any([", "any(")
+        .replace("])", ")")
+        .replace("Bool(true)", "true")
+        .replace("Bool(false)", "false");
+    if trace.contains(['{', '[', ']']) || trace.contains("Version(") {
+        debug!(attr, "Couldn't convert CfgTrace to cfg attributes");
+        return None;
+    }
+
+    // Each predicate in the trace is a separate `#[cfg]` attribute
+    let mut cfgs = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in trace.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                cfgs.push(format!("#[cfg({})]", trace[start..i].trim()));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    cfgs.push(format!("#[cfg({})]", trace[start..].trim()));
+    Some(cfgs)
+}
+
+/// Removes items and paths of kinds that don't exist in format version 26, and any references to the removed items
+fn remove_unknown_kinds(json: &mut Value) {
+    let is_known = |kind: &str| KNOWN_KINDS.contains(&kind);
+
+    if let Some(Value::Object(paths)) = json.get_mut("paths") {
+        paths.retain(|_, summary| summary["kind"].as_str().is_some_and(is_known));
+    }
+
+    let mut removed_ids = vec![];
+    if let Some(Value::Object(index)) = json.get_mut("index") {
+        index.retain(|id, item| {
+            let kind = match &item["inner"] {
+                Value::String(kind) => Some(kind.as_str()),
+                Value::Object(inner) => inner.keys().next().map(|k| k.as_str()),
+                _ => None,
+            };
+            let known = kind.is_some_and(is_known);
+            if !known {
+                debug!(id, kind, "Removing item with unknown kind");
+                removed_ids.push(Value::String(id.to_owned()));
+            }
+            known
+        });
+    }
+    if !removed_ids.is_empty() {
+        remove_references(json, &removed_ids);
+    }
+}
+
+/// Removes IDs from arrays (such as the items of a module), so there are no references to items that don't exist
+fn remove_references(value: &mut Value, removed_ids: &[Value]) {
+    match value {
+        Value::Array(array) => {
+            array.retain(|v| !removed_ids.contains(v));
+            for element in array {
+                remove_references(element, removed_ids);
+            }
+        }
+        Value::Object(object) => {
+            for value in object.values_mut() {
+                remove_references(value, removed_ids);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustdoc_types::{Item, ItemEnum, Type};
+
+    use super::*;
+
+    /// The JSON nightly-2026-05-19 generated for `fixtures/format_57.rs` (as `src/lib.rs`) with `--document-private-items`.
+    /// Only the paths of other crates and the list of other crates were removed
+    const FORMAT_57: &str = include_str!("fixtures/format_57.json");
+
+    fn item<'a>(krate: &'a Crate, name: &str) -> &'a Item {
+        krate
+            .index
+            .values()
+            .find(|i| i.name.as_deref() == Some(name))
+            .unwrap_or_else(|| panic!("{name} should be in the index"))
+    }
+
+    #[test]
+    fn format_26_is_not_converted() {
+        let krate = deserialize_rustdoc_json(FORMAT_57).unwrap();
+        assert_eq!(krate.format_version, 26);
+        let json = serde_json::to_string(&krate).unwrap();
+        assert_eq!(deserialize_rustdoc_json(&json).unwrap(), krate);
+    }
+
+    #[test]
+    fn converts_renamed_kinds() {
+        let krate = deserialize_rustdoc_json(FORMAT_57).unwrap();
+        assert!(matches!(item(&krate, "Alias").inner, ItemEnum::Typedef(_)));
+        assert!(matches!(
+            &item(&krate, "CONSTANT").inner,
+            ItemEnum::Constant(constant) if constant.expr == "1"
+        ));
+        assert!(matches!(
+            &item(&krate, "ASSOC").inner,
+            ItemEnum::AssocConst { default: Some(default), .. } if default == "2"
+        ));
+        let import = krate
+            .index
+            .values()
+            .find_map(|i| match &i.inner {
+                ItemEnum::Import(import) => Some(import),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(import.name, "renamed");
+        assert_eq!(import.source, "inner::inner");
+    }
+
+    #[test]
+    fn converts_signatures() {
+        let krate = deserialize_rustdoc_json(FORMAT_57).unwrap();
+        let ItemEnum::Function(function) = &item(&krate, "function").inner else {
+            panic!("function should be a function");
+        };
+        let Type::BorrowedRef { mutable, .. } = &function.decl.inputs[1].1 else {
+            panic!("the second argument should be a reference");
+        };
+        assert!(mutable);
+        // The `use<N>` bound doesn't exist in format version 26
+        let Some(Type::ImplTrait(bounds)) = &function.decl.output else {
+            panic!("the function should return an impl trait");
+        };
+        assert_eq!(bounds.len(), 1);
+        let ItemEnum::Function(foreign) = &item(&krate, "foreign").inner else {
+            panic!("foreign should be a function");
+        };
+        assert!(foreign.header.unsafe_);
+    }
+
+    #[test]
+    fn converts_attributes() {
+        let krate = deserialize_rustdoc_json(FORMAT_57).unwrap();
+        assert_eq!(
+            item(&krate, "Point").attrs,
+            ["#[must_use]", "#[non_exhaustive]"]
+        );
+        assert_eq!(
+            item(&krate, "without_a").attrs,
+            [r#"#[cfg(not(any(feature = "a", test)))]"#]
+        );
+    }
+
+    #[test]
+    fn converts_cfg_traces() {
+        let trace = r#"#[attr = CfgTrace([NameValue { name: "feature", value: Some("a"), span: src/lib.rs:1:7: 1:20 (#0) }, All([NameValue { name: "unix", value: None, span: src/lib.rs:2:11: 2:15 (#0) }, Not(Bool(false), src/lib.rs:2:17: 2:27 (#0))], src/lib.rs:2:7: 2:28 (#0))])]"#;
+        assert_eq!(
+            cfg_trace_to_cfgs(trace).unwrap(),
+            [r#"#[cfg(feature = "a")]"#, "#[cfg(all(unix, not(false)))]"]
+        );
+        assert_eq!(cfg_trace_to_cfgs("#[attr = TrackCaller]"), None);
+    }
+
+    #[test]
+    fn rejects_unsupported_format_versions() {
+        for format_version in [25, 27, 45, 56, 58] {
+            let json = FORMAT_57.replacen(
+                "\"format_version\": 57",
+                &format!("\"format_version\": {format_version}"),
+                1,
+            );
+            assert!(deserialize_rustdoc_json(&json).is_err());
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::error::Error;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use normalize_path::NormalizePath;
+use rustdoc_types::{Crate, Item, ItemEnum};
+use tracing::info;
+use xshell::Shell;
+
+mod format_version;
+use self::format_version::deserialize_rustdoc_json;
+
+/// The directory in the markdown directory where to-markdown stores the rustdoc JSON the markdown files were generated from.
+/// from-markdown reads the JSON from here, so it uses the same rustdoc IDs even if the JSON came from stdin or rustdoc has been re-run since.
+/// The rustdoc JSON is stored next to the output directory instead of in it, since static site generators publish every file in the output directory
+const RUSTDOC_JSON_DIR_SUFFIX: &str = "-rustdoc";
+
+/// Passing this as a path reads the JSON from stdin
+const STDIN_PATH: &str = "-";
+
+/// Reads and deserializes rustdoc JSON from each path. `-` reads the JSON from stdin.
+/// JSON in a newer supported format version is converted first, see [`format_version`]
+#[wrap_match::wrap_match(log_success = false)]
+pub fn read_rustdoc_json(sh: &Shell, paths: &[PathBuf]) -> Result<Vec<Crate>, Box<dyn Error>> {
+    let mut crates = vec![];
+    for path in paths {
+        let json = if path == Path::new(STDIN_PATH) {
+            info!("Reading JSON from stdin");
+            let mut json = String::new();
+            std::io::stdin().read_to_string(&mut json)?;
+            json
+        } else {
+            info!("Reading JSON from {}", path.display());
+            sh.read_file(path)?
+        };
+
+        info!("Deserializing JSON");
+        crates.push(deserialize_rustdoc_json(&json)?);
+    }
+    Ok(crates)
+}
+
+/// The directory the rustdoc JSON used for the markdown files in `output_dir` is stored in, such as `target/doc-sync-rustdoc`
+pub fn rustdoc_json_dir(output_dir: &Path) -> PathBuf {
+    let output_dir_name = output_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "doc-sync".to_owned());
+    output_dir.with_file_name(format!("{output_dir_name}{RUSTDOC_JSON_DIR_SUFFIX}"))
+}
+
+/// Writes the JSON of the crate to the rustdoc JSON directory of `output_dir`. `subdirectory` is the directory in the output directory the markdown files of the crate are in
+#[wrap_match::wrap_match(log_success = false)]
+pub fn store_rustdoc_json(
+    sh: &Shell,
+    output_dir: &Path,
+    subdirectory: &Path,
+    json: &Crate,
+    feature_set: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let path = rustdoc_json_dir(output_dir)
+        .join(subdirectory)
+        .join(json_file_name(crate_name(json), feature_set));
+    sh.write_file(path, serde_json::to_string(json)?)?;
+    Ok(())
+}
+
+/// The paths of the JSON files stored by to-markdown for `input_dir`, including the ones of targets that aren't libraries
+#[wrap_match::wrap_match(log_success = false)]
+pub fn stored_rustdoc_json(sh: &Shell, input_dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let json_dir = rustdoc_json_dir(input_dir);
+    if !sh.path_exists(&json_dir) {
+        return Ok(vec![]);
+    }
+    let mut dirs = vec![json_dir.clone()];
+    dirs.extend(sh.read_dir(&json_dir)?.into_iter().filter(|p| p.is_dir()));
+
+    let mut paths = vec![];
+    for dir in dirs {
+        paths.extend(
+            sh.read_dir(dir)?
+                .into_iter()
+                .filter(|p| p.extension().is_some_and(|e| e == "json")),
+        );
+    }
+    Ok(paths)
+}
+
+/// The directory in `input_dir` the markdown files of a JSON file are in, if it was stored by to-markdown. Both paths must be absolute
+pub fn subdirectory_of_stored_json(input_dir: &Path, path: &Path) -> Option<PathBuf> {
+    path.normalize()
+        .parent()?
+        .strip_prefix(rustdoc_json_dir(&input_dir.normalize()))
+        .ok()
+        .map(|subdirectory| subdirectory.to_path_buf())
+}
+
+/// The name of the crate as used by rustdoc (with dashes replaced with underscores)
+pub fn crate_name(json: &Crate) -> &str {
+    json.index
+        .get(&json.root)
+        .and_then(|i| i.name.as_deref())
+        .expect("rustdoc JSON output is invalid?")
+}
+
+/// The root file of the crate (for example `src/lib.rs`)
+pub fn root_file(json: &Crate) -> Option<&Path> {
+    json.index
+        .get(&json.root)
+        .and_then(|i| i.span.as_ref())
+        .map(|span| span.filename.as_path())
+}
+
+/// The name of an item in the exported file layout. Imports and impl blocks don't have a name in the rustdoc JSON,
+/// so imports are named after the name they import and impl blocks after their trait (`impl-Display`, or `impl` for inherent impls).
+/// `extern crate`s are named after the name the crate is imported as, which is what rustdoc names them (rustdoc swaps `name` and `rename`).
+/// Returns `None` for impls generated by rustdoc or derives (auto trait, blanket and derived impls), since they don't have docs in the source
+pub fn item_name(item: &Item) -> Option<String> {
+    match &item.inner {
+        ItemEnum::Import(import) => Some(import.name.clone()),
+        ItemEnum::ExternCrate { name, .. } => {
+            Some(item.name.clone().unwrap_or_else(|| name.clone()))
+        }
+        ItemEnum::Impl(impl_) => {
+            if impl_.synthetic
+                || impl_.blanket_impl.is_some()
+                || item
+                    .attrs
+                    .iter()
+                    .any(|a| a.contains("automatically_derived"))
+            {
+                return None;
+            }
+            Some(match &impl_.trait_ {
+                Some(trait_) => format!("impl-{}", trait_.name.rsplit("::").next().unwrap()),
+                None => "impl".to_owned(),
+            })
+        }
+        _ => item.name.clone(),
+    }
+}
+
+/// The name of the feature set with the given comma-separated features, as used in file names and metadata.
+/// Features can contain `/` (such as `serde/std`) and `:` (such as `dep:serde`), which are replaced since they can't be in file names
+pub fn feature_set_name(features: &str) -> String {
+    if features.trim().is_empty() {
+        return "default".to_owned();
+    }
+    features
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | '+' | '.' | ',' => c,
+            _ => '-',
+        })
+        .collect()
+}
+
+/// The file name of the JSON of a crate. JSON generated with a feature set has the name of the feature set after a `~`
+pub fn json_file_name(crate_name: &str, feature_set: Option<&str>) -> String {
+    match feature_set {
+        Some(feature_set) => format!("{crate_name}~{feature_set}.json"),
+        None => format!("{crate_name}.json"),
+    }
+}
+
+/// The name of the feature set a JSON file was generated with, based on its file name (see [`json_file_name`])
+pub fn feature_set_of_json_file(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?;
+    stem.split_once('~')
+        .map(|(_, feature_set)| feature_set.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feature_set_names_are_file_names() {
+        assert_eq!(feature_set_name(""), "default");
+        assert_eq!(feature_set_name(" "), "default");
+        assert_eq!(feature_set_name("serde, async"), "serde,async");
+        assert_eq!(
+            feature_set_name("serde/std,dep:tokio"),
+            "serde-std,dep-tokio"
+        );
+        assert_eq!(feature_set_name(r#"a\b~c;d"e"#), "a-b-c-d-e");
+    }
+
+    #[test]
+    fn feature_set_of_json_file_name() {
+        let name = json_file_name("my_crate", Some(&feature_set_name("serde/std")));
+        assert_eq!(name, "my_crate~serde-std.json");
+        assert_eq!(
+            feature_set_of_json_file(Path::new(&name)).as_deref(),
+            Some("serde-std")
+        );
+        assert_eq!(feature_set_of_json_file(Path::new("my_crate.json")), None);
+    }
+
+    #[test]
+    fn stored_json_is_next_to_the_output_directory() {
+        let output_dir = Path::new("/crate/./target/doc-sync");
+        let json_dir = rustdoc_json_dir(output_dir);
+        assert_eq!(json_dir, Path::new("/crate/./target/doc-sync-rustdoc"));
+        assert_eq!(
+            subdirectory_of_stored_json(output_dir, &json_dir.join("my_crate.json")),
+            Some(PathBuf::new())
+        );
+        assert_eq!(
+            subdirectory_of_stored_json(output_dir, &json_dir.join("bin/my_crate.json")),
+            Some(PathBuf::from("bin"))
+        );
+        assert_eq!(
+            subdirectory_of_stored_json(output_dir, Path::new("/crate/target/doc/my_crate.json")),
+            None
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! Fingerprinting of everything that affects the rustdoc JSON, so `cargo doc` can be skipped when nothing changed since the last run
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use normalize_path::NormalizePath;
+use sha2::{Digest, Sha256};
+use tracing::debug;
+use xshell::{cmd, Shell};
+
+use super::layout::generated_paths;
+use crate::rustdoc_json::rustdoc_json_dir;
+
+/// The fingerprint is stored next to the JSON in a file with this extension added to the path of the JSON
+const FINGERPRINT_EXTENSION: &str = "doc-sync-fingerprint";
+
+/// Directories of the package that don't affect the rustdoc JSON
+const IGNORED_DIRS: &[&str] = &["target", ".git"];
+
+/// Hashes every file of the package, the toolchain and the arguments used to run cargo doc.
+/// Features are passed through the cargo doc arguments, so they are part of the fingerprint too.
+/// The crate can read files from anywhere in the package (through `#[path]`, a custom target path, `include!` or a build script),
+/// so every file is hashed except for the ones in `target` and the ones generated by doc-sync.
+#[wrap_match::wrap_match(log_success = false)]
+pub fn fingerprint(
+    sh: &Shell,
+    output_dir: &Path,
+    cargo_arguments: &str,
+    cargo_doc_arguments: &[String],
+    rustdoc_arguments: &str,
+) -> Result<String, Box<dyn Error>> {
+    let mut hasher = Sha256::new();
+
+    // `--verbose` includes the commit hash, so different nightlies have different fingerprints
+    let toolchain = cmd!(sh, "cargo {cargo_arguments} --version --verbose").read()?;
+    debug!(toolchain);
+    hash_field(&mut hasher, "toolchain", toolchain.as_bytes());
+    hash_field(&mut hasher, "cargo arguments", cargo_arguments.as_bytes());
+    hash_field(
+        &mut hasher,
+        "cargo doc arguments",
+        cargo_doc_arguments.join("\0").as_bytes(),
+    );
+    hash_field(
+        &mut hasher,
+        "rustdoc arguments",
+        rustdoc_arguments.as_bytes(),
+    );
+
+    let package_dir = sh.current_dir();
+    let generated_paths: Vec<_> = generated_paths(output_dir)
+        .into_iter()
+        .chain([rustdoc_json_dir(output_dir)])
+        .map(|path| package_dir.join(path).normalize())
+        .collect();
+    let mut files = vec![];
+    package_files(sh, &package_dir, &generated_paths, &mut files)?;
+    for file in files {
+        let relative_path = file.strip_prefix(&package_dir).unwrap_or(&file);
+        hash_field(
+            &mut hasher,
+            &relative_path.display().to_string(),
+            &sh.read_binary_file(&file)?,
+        );
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// The files in `dir`, sorted so the fingerprint doesn't depend on the order of the directory entries.
+/// Symlinked directories aren't followed, since they could point to a parent directory
+#[wrap_match::wrap_match(log_success = false)]
+fn package_files(
+    sh: &Shell,
+    dir: &Path,
+    generated_paths: &[PathBuf],
+    files: &mut Vec<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    for path in sh.read_dir(dir)? {
+        let path = path.normalize();
+        if generated_paths.contains(&path) || path.is_symlink() && path.is_dir() {
+            continue;
+        }
+        if path.is_dir() {
+            let ignored = path
+                .file_name()
+                .is_some_and(|name| IGNORED_DIRS.iter().any(|ignored| name == *ignored));
+            if !ignored {
+                package_files(sh, &path, generated_paths, files)?;
+            }
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Hashes the length of each part too, so moving bytes from one part to another changes the fingerprint
+fn hash_field(hasher: &mut Sha256, name: &str, contents: &[u8]) {
+    hasher.update((name.len() as u64).to_le_bytes());
+    hasher.update(name);
+    hasher.update((contents.len() as u64).to_le_bytes());
+    hasher.update(contents);
+}
+
+fn fingerprint_path(json_path: &Path) -> PathBuf {
+    let mut path = json_path.as_os_str().to_owned();
+    path.push(".");
+    path.push(FINGERPRINT_EXTENSION);
+    path.into()
+}
+
+/// The fingerprint of the inputs followed by a hash of the JSON itself. The JSON can be overwritten by other runs of cargo doc
+/// (such as the runs for other feature sets, or a `cargo doc` run by hand with different flags), in which case it isn't up to date anymore
+fn stored_fingerprint(
+    sh: &Shell,
+    json_path: &Path,
+    fingerprint: &str,
+) -> Result<String, Box<dyn Error>> {
+    let json_hash = Sha256::digest(sh.read_binary_file(json_path)?);
+    Ok(format!("{fingerprint}\n{json_hash:x}"))
+}
+
+/// Whether the JSON at `json_path` was generated from inputs with the same fingerprint and hasn't been overwritten since
+pub fn is_up_to_date(sh: &Shell, json_path: &Path, fingerprint: &str) -> bool {
+    let Ok(stored) = sh.read_file(fingerprint_path(json_path)) else {
+        return false;
+    };
+    match stored_fingerprint(sh, json_path, fingerprint) {
+        Ok(expected) => stored == expected,
+        Err(_) => false,
+    }
+}
+
+/// Stores the fingerprint of the inputs the JSON at `json_path` was generated from
+#[wrap_match::wrap_match(log_success = false)]
+pub fn store_fingerprint(
+    sh: &Shell,
+    json_path: &Path,
+    fingerprint: &str,
+) -> Result<(), Box<dyn Error>> {
+    sh.write_file(
+        fingerprint_path(json_path),
+        stored_fingerprint(sh, json_path, fingerprint)?,
+    )?;
+    Ok(())
+}
@@ -1,51 +1,96 @@
+use std::collections::HashSet;
 use std::error::Error;
-use std::path::PathBuf;
 
-use rustdoc_types::{Crate, Id, Item, ItemKind};
+use rustdoc_types::{Crate, Id, Item, ItemEnum, ItemKind};
 use tracing::{debug, warn};
-use xshell::Shell;
 
-use crate::consts::*;
+use crate::consts::MODULE_DOCS_SEPARATOR;
 use crate::edit_docs;
+use crate::from_markdown::ModuleTree;
 
 use super::iterate_children::iterate_children;
+use super::markdown_file::MarkdownFile;
 
 pub fn handle_item<'rustdoc>(
-    sh: &Shell,
     json: &'rustdoc Crate,
-    output_dir: &PathBuf,
-    handled_ids: &mut Vec<&'rustdoc String>,
+    files: &mut Vec<MarkdownFile<'rustdoc>>,
+    handled_ids: &mut HashSet<&'rustdoc String>,
     id: &'rustdoc Id,
     item: &'rustdoc Item,
     path: &Vec<String>,
     kind: &ItemKind,
 ) -> Result<(), Box<dyn Error>> {
-    if handled_ids.contains(&&id.0) {
+    if handled_ids.contains(&id.0) {
         return Ok(());
     }
 
     let mut file_path = path.join("/");
-    file_path.push_str(&format!("~{kind:?}.md"));
-
-    let mut docs: Vec<_> = item
-        .docs
-        .clone()
-        .unwrap_or_default()
-        .lines()
-        .map(|l| l.to_owned())
-        .collect();
-    edit_docs::to_markdown(&mut docs);
-    let mut docs = docs.join("\n");
-    docs.insert_str(0, &format!("{METADATA_COMMENT_PREFIX}{METADATA_ID_PREFIX}{}{METADATA_ID_SUFFIX}{METADATA_COMMENT_SUFFIX}\n\n", id.0));
+    file_path.push_str(&format!("~{kind:?}"));
 
-    if sh.path_exists(&file_path) {
-        warn!(file_path, "Rare edge case has occurred! Somehow you have triggered a conflict. Two items have the same path, the currently existing file will be overwritten");
-    }
-    debug!(file_path, "Generating");
-    sh.write_file(output_dir.join(file_path), docs)?;
-    handled_ids.push(&id.0);
+    let docs: Vec<_> = item.docs.as_deref().unwrap_or_default().lines().collect();
+
+    debug!(file_path, "Collecting");
+    files.push(MarkdownFile {
+        path: file_path,
+        id: &id.0,
+        location: item
+            .span
+            .as_ref()
+            .map(|span| (span.filename.as_path(), span.begin.0)),
+        docs: docs_to_markdown(&docs),
+        read_only: None,
+        header: None,
+    });
+    handled_ids.insert(&id.0);
 
-    iterate_children(sh, json, output_dir, handled_ids, path, &item.inner)?;
+    iterate_children(json, files, handled_ids, path, &item.inner)?;
 
     Ok(())
 }
+
+/// rustdoc merges the outer docs of a `mod foo;` declaration with the inner docs of the module file.
+/// The markdown files of such modules get a separator between the two parts, so each part can be written back to where it came from
+pub fn split_module_docs(json: &Crate, modules: &ModuleTree, files: &mut [MarkdownFile]) {
+    for file in files {
+        let Some(item) = json.index.get(&Id(file.id.to_owned())) else {
+            continue;
+        };
+        let (ItemEnum::Module(_), Some(span), Some(summary)) =
+            (&item.inner, &item.span, json.paths.get(&item.id))
+        else {
+            continue;
+        };
+        let Some(declaration) = modules.declaration(&span.filename, &summary.path) else {
+            continue;
+        };
+        if declaration.outer_doc_lines == 0 {
+            continue;
+        }
+
+        let docs: Vec<_> = item.docs.as_deref().unwrap_or_default().lines().collect();
+        if docs.len() < declaration.outer_doc_lines {
+            warn!(
+                path = file.path,
+                "The module has fewer lines of docs than its mod declaration, so the docs of the declaration and the module file can't be told apart"
+            );
+            continue;
+        }
+        let (outer_docs, inner_docs) = docs.split_at(declaration.outer_doc_lines);
+        debug!(
+            path = file.path,
+            declaration = debug(declaration),
+            "Splitting module docs"
+        );
+        file.docs = format!(
+            "{}\n\n{MODULE_DOCS_SEPARATOR}\n\n{}",
+            docs_to_markdown(outer_docs),
+            docs_to_markdown(inner_docs)
+        );
+    }
+}
+
+fn docs_to_markdown(docs: &[&str]) -> String {
+    let mut docs: Vec<_> = docs.iter().map(|l| (*l).to_owned()).collect();
+    edit_docs::to_markdown(&mut docs);
+    docs.join("\n")
+}
@@ -0,0 +1,227 @@
+//! The header at the top of each markdown file shows what the docs are for while writing them.
+//! It is generated from the rustdoc JSON and the source, and from-markdown removes it again
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use rustdoc_types::{Crate, Id, Item, ItemEnum, Span, Visibility};
+use tracing::warn;
+use xshell::Shell;
+
+use super::item_enum_ext::ItemEnumExt;
+use super::markdown_file::MarkdownFile;
+
+pub fn add_headers(sh: &Shell, json: &Crate, files: &mut [MarkdownFile]) {
+    let mut sources: HashMap<PathBuf, Option<String>> = HashMap::new();
+    for file in files {
+        let Some(item) = json.index.get(&Id(file.id.to_owned())) else {
+            continue;
+        };
+        let item_path = file.path.split('~').next().unwrap().replace('/', "::");
+        let mut header = format!("**{:?}** `{item_path}`", item.inner.to_item_kind());
+        if let Some(visibility) = visibility(&item.visibility) {
+            header.push_str(&format!(" (`{visibility}`)"));
+        }
+
+        let declaration = item.span.as_ref().and_then(|span| {
+            let source = sources.entry(span.filename.clone()).or_insert_with(|| {
+                match sh.read_file(&span.filename) {
+                    Ok(contents) => Some(contents),
+                    Err(e) => {
+                        warn!(
+                            file = display(span.filename.display()),
+                            "Couldn't read source file for the header: {e}"
+                        );
+                        None
+                    }
+                }
+            });
+            declaration(item, span, source.as_deref()?)
+        });
+        if let Some(declaration) = declaration {
+            header.push_str(&format!("\n\n```rust\n{declaration}\n```"));
+        }
+        file.header = Some(header);
+    }
+}
+
+fn visibility(visibility: &Visibility) -> Option<String> {
+    match visibility {
+        Visibility::Public => Some("pub".to_owned()),
+        // Items whose visibility is the visibility of their parent, such as enum variants and trait items
+        Visibility::Default => None,
+        Visibility::Crate => Some("pub(crate)".to_owned()),
+        Visibility::Restricted { path, .. } => Some(format!("pub(in {path})")),
+    }
+}
+
+/// The source of the item without its docs. The bodies of functions, impls, traits, inline modules and macros are left out since they aren't part of the declaration
+fn declaration(item: &Item, span: &Span, source: &str) -> Option<String> {
+    if let ItemEnum::Module(_) = item.inner {
+        // The span of a module is the whole module
+        return None;
+    }
+    let lines: Vec<_> = source
+        .lines()
+        .skip(span.begin.0.checked_sub(1)?)
+        .take(span.end.0 + 1 - span.begin.0)
+        .collect();
+    let last = lines.len().checked_sub(1)?;
+    let mut text = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        // Columns are 1-based and the end is exclusive
+        let end = if i == last {
+            line.char_indices()
+                .nth(span.end.1.saturating_sub(1))
+                .map_or(line.len(), |(end, _)| end)
+        } else {
+            line.len()
+        };
+        let start = if i == 0 {
+            line.char_indices()
+                .nth(span.begin.1.saturating_sub(1))
+                .map_or(end, |(start, _)| start)
+                .min(end)
+        } else {
+            0
+        };
+        let line = &line[start..end];
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("///") || trimmed.starts_with("//!") {
+            continue;
+        }
+        text.push_str(line);
+        text.push('\n');
+    }
+    let text = text.trim_end().trim_end_matches(',');
+
+    let has_body = matches!(
+        item.inner,
+        ItemEnum::Function(_) | ItemEnum::Impl(_) | ItemEnum::Trait(_) | ItemEnum::Macro(_)
+    );
+    let text = match text.find('{') {
+        Some(body) if has_body => format!("{} {{ ... }}", text[..body].trim_end()),
+        _ => text.to_owned(),
+    };
+    Some(dedent(&text))
+}
+
+/// Removes the indentation of the item from every line but the first, which doesn't have it since the span starts after it
+fn dedent(text: &str) -> String {
+    let indentation = text
+        .lines()
+        .skip(1)
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    // The last line of a multi-line item (such as the closing brace) has the indentation of the item
+    let item_indentation = text
+        .lines()
+        .last()
+        .filter(|_| text.lines().count() > 1)
+        .map_or(0, |l| l.len() - l.trim_start().len())
+        .min(indentation);
+    text.lines()
+        .enumerate()
+        .map(|(i, l)| {
+            if i == 0 {
+                l
+            } else {
+                l.get(item_indentation..).unwrap_or(l.trim_start())
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use rustdoc_types::{Generics, Struct, StructKind};
+
+    use super::*;
+    use crate::from_markdown::test_utils::{crate_root, rustdoc_item};
+
+    fn span(begin: (usize, usize), end: (usize, usize)) -> Span {
+        Span {
+            filename: PathBuf::from("src/lib.rs"),
+            begin,
+            end,
+        }
+    }
+
+    #[test]
+    fn shows_restricted_visibility() {
+        assert_eq!(visibility(&Visibility::Public).unwrap(), "pub");
+        assert_eq!(visibility(&Visibility::Crate).unwrap(), "pub(crate)");
+        assert_eq!(
+            visibility(&Visibility::Restricted {
+                parent: Id("0:1".to_owned()),
+                path: "crate::module".to_owned(),
+            })
+            .unwrap(),
+            "pub(in crate::module)"
+        );
+        assert!(visibility(&Visibility::Default).is_none());
+    }
+
+    #[test]
+    fn declarations_leave_out_docs_and_the_indentation_of_the_item() {
+        let source = "\
+mod inline {
+    /// Docs
+    #[derive(Debug)]
+    pub struct Foo {
+        /// Docs of the field
+        pub field: u8,
+    }
+}
+";
+        let item = rustdoc_item(
+            "0:1",
+            Some("Foo"),
+            ItemEnum::Struct(Struct {
+                kind: StructKind::Plain {
+                    fields: vec![],
+                    fields_stripped: false,
+                },
+                generics: Generics {
+                    params: vec![],
+                    where_predicates: vec![],
+                },
+                impls: vec![],
+            }),
+        );
+        assert_eq!(
+            declaration(&item, &span((4, 5), (7, 6)), source).unwrap(),
+            "pub struct Foo {\n    pub field: u8,\n}"
+        );
+    }
+
+    #[test]
+    fn declarations_leave_out_bodies() {
+        let source = "macro_rules! foo { () => {} }\nmod bar {}\n";
+        let item = rustdoc_item(
+            "0:1",
+            Some("foo"),
+            ItemEnum::Macro("macro_rules! foo { ... }".to_owned()),
+        );
+        assert_eq!(
+            declaration(&item, &span((1, 1), (1, 30)), source).unwrap(),
+            "macro_rules! foo { ... }"
+        );
+        let module = crate_root();
+        assert!(declaration(&module, &span((2, 1), (2, 11)), source).is_none());
+    }
+
+    #[test]
+    fn dedent_keeps_the_indentation_inside_the_item() {
+        assert_eq!(
+            dedent("enum Foo {\n        A,\n            B,\n    }"),
+            "enum Foo {\n    A,\n        B,\n}"
+        );
+        assert_eq!(dedent("const FOO: u8 = 1;"), "const FOO: u8 = 1;");
+    }
+}
@@ -0,0 +1,226 @@
+//! Every directory of the output directory gets an index file listing the items in it, since a directory of `name~Kind.md` files is hard to get an overview of.
+//! The index files are generated, so from-markdown ignores them
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+use tracing::debug;
+use xshell::Shell;
+
+use crate::consts::{INDEX_FILE_HEADER, INDEX_FILE_NAME};
+
+use super::markdown_file::MarkdownFile;
+
+/// `files` are the written markdown files and their paths relative to the output directory
+#[wrap_match::wrap_match(log_success = false)]
+pub fn write_index_files(
+    sh: &Shell,
+    output_dir: &Path,
+    files: &[(String, &MarkdownFile)],
+) -> Result<(), Box<dyn Error>> {
+    let mut children_by_dir: BTreeMap<&str, Vec<(&str, &MarkdownFile)>> = BTreeMap::new();
+    for (file_path, file) in files {
+        // The top level only has the crate roots
+        if let Some((dir, name)) = file_path.rsplit_once('/') {
+            children_by_dir.entry(dir).or_default().push((name, file));
+        }
+    }
+
+    for (dir, children) in &children_by_dir {
+        let (parent_dir, dir_name) = dir.rsplit_once('/').unwrap_or(("", dir));
+        let mut index = format!("{INDEX_FILE_HEADER}\n\n# `{}`\n", dir.replace('/', "::"));
+        // The markdown files of the item itself are next to the directory
+        for (file_path, _) in files {
+            let (file_dir, name) = file_path.rsplit_once('/').unwrap_or(("", file_path));
+            if file_dir == parent_dir && name.split('~').next() == Some(dir_name) {
+                index.push_str(&format!("\nDocs: [{name}](../{name})\n"));
+            }
+        }
+
+        for (kind, children) in group_by_kind(children) {
+            index.push_str(&format!("\n## {kind}\n\n"));
+            for (name, file) in children {
+                let mut parts = name.trim_end_matches(".md").split('~');
+                let mut item_name = format!("`{}`", parts.next().unwrap());
+                let variant: Vec<_> = parts.skip(1).collect();
+                if !variant.is_empty() {
+                    item_name.push_str(&format!(" ({})", variant.join("~")));
+                }
+                let summary = match summary(&file.docs) {
+                    Some(summary) => summary,
+                    None => "**Undocumented**".to_owned(),
+                };
+                index.push_str(&format!("- [{item_name}]({name}): {summary}"));
+                let stem = name.split('~').next().unwrap();
+                // A glob import is named after the module it imports, which can be next to it
+                let has_children = !matches!(kind, "Import" | "ExternCrate");
+                if has_children && children_by_dir.contains_key(format!("{dir}/{stem}").as_str()) {
+                    index.push_str(&format!(" ([children]({stem}/{INDEX_FILE_NAME}))"));
+                }
+                index.push('\n');
+            }
+        }
+
+        let index_path = output_dir.join(dir).join(INDEX_FILE_NAME);
+        debug!(
+            index_path = display(index_path.display()),
+            "Generating index"
+        );
+        sh.write_file(index_path, index)?;
+    }
+
+    Ok(())
+}
+
+/// Groups the children by the kind in their file name. The groups and the children in them are in source order
+fn group_by_kind<'lt>(
+    children: &[(&'lt str, &'lt MarkdownFile)],
+) -> Vec<(&'lt str, Vec<(&'lt str, &'lt MarkdownFile<'lt>)>)> {
+    let mut children = children.to_vec();
+    // Items without a location come last
+    children.sort_by_key(|(name, file)| (file.location.is_none(), file.location, *name));
+    let mut groups: Vec<(&str, Vec<_>)> = vec![];
+    for (name, file) in children {
+        let kind = name
+            .trim_end_matches(".md")
+            .split('~')
+            .nth(1)
+            .unwrap_or_default();
+        match groups.iter_mut().find(|(k, _)| *k == kind) {
+            Some((_, group)) => group.push((name, file)),
+            None => groups.push((kind, vec![(name, file)])),
+        }
+    }
+    groups
+}
+
+/// The first sentence of the first paragraph of the docs
+fn summary(docs: &str) -> Option<String> {
+    let paragraph: Vec<_> = docs
+        .lines()
+        .map(|l| l.trim())
+        .skip_while(|l| l.is_empty() || l.starts_with("<!--"))
+        .take_while(|l| !l.is_empty() && !l.starts_with("<!--"))
+        .map(|l| l.trim_start_matches('#').trim())
+        .collect();
+    let paragraph = paragraph.join(" ");
+    if paragraph.is_empty() {
+        return None;
+    }
+    Some(match paragraph.find(". ") {
+        Some(end) => paragraph[..=end].to_owned(),
+        None => paragraph,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn markdown_file(docs: &str, line: Option<usize>) -> MarkdownFile<'static> {
+        MarkdownFile {
+            path: String::new(),
+            id: "0:0",
+            location: line.map(|line| (Path::new("src/lib.rs"), line)),
+            docs: docs.to_owned(),
+            read_only: None,
+            header: None,
+        }
+    }
+
+    #[test]
+    fn summaries_are_the_first_sentence() {
+        assert_eq!(
+            summary("<!-- comment -->\n\n# Title\n\nFirst sentence. Second sentence.").unwrap(),
+            "Title"
+        );
+        assert_eq!(
+            summary("First sentence\nover two lines. Second sentence.\n\nSecond paragraph")
+                .unwrap(),
+            "First sentence over two lines."
+        );
+        assert_eq!(summary("Version 1.0 is out").unwrap(), "Version 1.0 is out");
+        assert!(summary("\n\n").is_none());
+    }
+
+    #[test]
+    fn groups_children_by_kind_in_source_order() {
+        let (a, b, c, d) = (
+            markdown_file("", Some(5)),
+            markdown_file("", Some(1)),
+            markdown_file("", None),
+            markdown_file("", Some(3)),
+        );
+        let children = [
+            ("a~Function.md", &a),
+            ("b~Struct.md", &b),
+            ("c~Function.md", &c),
+            ("d~Function.md", &d),
+        ];
+        let groups: Vec<_> = group_by_kind(&children)
+            .into_iter()
+            .map(|(kind, children)| {
+                (
+                    kind,
+                    children
+                        .into_iter()
+                        .map(|(name, _)| name)
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                ("Struct", vec!["b~Struct.md"]),
+                (
+                    "Function",
+                    vec!["d~Function.md", "a~Function.md", "c~Function.md"]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_an_index_for_every_directory() {
+        let sh = Shell::new().unwrap();
+        let output_dir =
+            std::env::temp_dir().join(format!("doc-sync-index-{}", std::process::id()));
+        let (root, module, function, variant) = (
+            markdown_file("The crate", Some(1)),
+            markdown_file("A module. With details.", Some(3)),
+            markdown_file("", Some(5)),
+            markdown_file("A variant", Some(7)),
+        );
+        let files = [
+            ("krate~Module.md".to_owned(), &root),
+            ("krate/module~Module.md".to_owned(), &module),
+            ("krate/module/foo~Function~a.md".to_owned(), &function),
+            ("krate/module/foo~Function~b.md".to_owned(), &variant),
+        ];
+        write_index_files(&sh, &output_dir, &files).unwrap();
+
+        assert_eq!(
+            sh.read_file(output_dir.join("krate").join(INDEX_FILE_NAME))
+                .unwrap(),
+            format!(
+                "{INDEX_FILE_HEADER}\n\n# `krate`\n\nDocs: [krate~Module.md](../krate~Module.md)\n\n\
+                ## Module\n\n- [`module`](module~Module.md): A module. ([children](module/{INDEX_FILE_NAME}))\n"
+            )
+        );
+        assert_eq!(
+            sh.read_file(output_dir.join("krate/module").join(INDEX_FILE_NAME))
+                .unwrap(),
+            format!(
+                "{INDEX_FILE_HEADER}\n\n# `krate::module`\n\nDocs: [module~Module.md](../module~Module.md)\n\n\
+                ## Function\n\n- [`foo` (a)](foo~Function~a.md): **Undocumented**\n\
+                - [`foo` (b)](foo~Function~b.md): A variant\n"
+            )
+        );
+        // The top level only has the crate roots, so it has no index
+        assert!(!sh.path_exists(output_dir.join(INDEX_FILE_NAME)));
+
+        sh.remove_path(&output_dir).unwrap();
+    }
+}
@@ -1,127 +1,88 @@
+use std::collections::HashSet;
 use std::error::Error;
-use std::path::PathBuf;
 
-use rustdoc_types::{Crate, Id, ItemEnum, VariantKind};
+use rustdoc_types::{Crate, Id, ItemEnum, StructKind, VariantKind};
 use tracing::trace;
-use xshell::Shell;
+
+use crate::rustdoc_json::item_name;
 
 use super::handle_item::handle_item;
 use super::item_enum_ext::ItemEnumExt;
+use super::markdown_file::MarkdownFile;
 
 pub fn iterate_children<'rustdoc>(
-    sh: &Shell,
     json: &'rustdoc Crate,
-    output_dir: &PathBuf,
-    handled_ids: &mut Vec<&'rustdoc String>,
+    files: &mut Vec<MarkdownFile<'rustdoc>>,
+    handled_ids: &mut HashSet<&'rustdoc String>,
     parent_path: &Vec<String>,
     item: &'rustdoc ItemEnum,
 ) -> Result<(), Box<dyn Error>> {
     match item {
+        // Impl blocks are exported with the type they are for
         ItemEnum::Module(item) => iterate_ids(
-            sh,
             json,
-            output_dir,
+            files,
             handled_ids,
             parent_path,
-            item.items.iter(),
+            item.items.iter().filter(|id| {
+                !matches!(
+                    json.index.get(id).map(|i| &i.inner),
+                    Some(ItemEnum::Impl(_))
+                )
+            }),
         ),
         ItemEnum::ExternCrate { .. } => Ok(()),
         ItemEnum::Import(_) => Ok(()),
 
         ItemEnum::Union(item) => {
-            iterate_ids(
-                sh,
-                json,
-                output_dir,
-                handled_ids,
-                parent_path,
-                item.fields.iter(),
-            )?;
-            iterate_ids(
-                sh,
-                json,
-                output_dir,
-                handled_ids,
-                parent_path,
-                item.impls.iter(),
-            )
+            iterate_ids(json, files, handled_ids, parent_path, item.fields.iter())?;
+            iterate_ids(json, files, handled_ids, parent_path, item.impls.iter())
+        }
+        ItemEnum::Struct(item) => {
+            match &item.kind {
+                StructKind::Unit => {}
+                StructKind::Tuple(fields) => iterate_ids(
+                    json,
+                    files,
+                    handled_ids,
+                    parent_path,
+                    fields.iter().filter_map(|f| f.as_ref()),
+                )?,
+                StructKind::Plain { fields, .. } => {
+                    iterate_ids(json, files, handled_ids, parent_path, fields.iter())?
+                }
+            }
+            iterate_ids(json, files, handled_ids, parent_path, item.impls.iter())
         }
-        ItemEnum::Struct(item) => iterate_ids(
-            sh,
-            json,
-            output_dir,
-            handled_ids,
-            parent_path,
-            item.impls.iter(),
-        ),
         ItemEnum::StructField(_) => Ok(()),
         ItemEnum::Enum(item) => {
-            iterate_ids(
-                sh,
-                json,
-                output_dir,
-                handled_ids,
-                parent_path,
-                item.variants.iter(),
-            )?;
-            iterate_ids(
-                sh,
-                json,
-                output_dir,
-                handled_ids,
-                parent_path,
-                item.impls.iter(),
-            )
+            iterate_ids(json, files, handled_ids, parent_path, item.variants.iter())?;
+            iterate_ids(json, files, handled_ids, parent_path, item.impls.iter())
         }
         ItemEnum::Variant(item) => match &item.kind {
             VariantKind::Plain => Ok(()),
             VariantKind::Tuple(fields) => iterate_ids(
-                sh,
                 json,
-                output_dir,
+                files,
                 handled_ids,
                 parent_path,
                 fields.iter().filter_map(|f| f.as_ref()),
             ),
-            VariantKind::Struct { fields, .. } => iterate_ids(
-                sh,
-                json,
-                output_dir,
-                handled_ids,
-                parent_path,
-                fields.iter(),
-            ),
+            VariantKind::Struct { fields, .. } => {
+                iterate_ids(json, files, handled_ids, parent_path, fields.iter())
+            }
         },
 
         ItemEnum::Function(_) => Ok(()),
 
+        // The implementations of the trait are exported with the type they are for
         ItemEnum::Trait(item) => {
-            iterate_ids(
-                sh,
-                json,
-                output_dir,
-                handled_ids,
-                parent_path,
-                item.items.iter(),
-            )?;
-            iterate_ids(
-                sh,
-                json,
-                output_dir,
-                handled_ids,
-                parent_path,
-                item.implementations.iter(),
-            )
+            iterate_ids(json, files, handled_ids, parent_path, item.items.iter())
         }
         ItemEnum::TraitAlias(_) => Ok(()),
-        ItemEnum::Impl(item) => iterate_ids(
-            sh,
-            json,
-            output_dir,
-            handled_ids,
-            parent_path,
-            item.items.iter(),
-        ),
+        ItemEnum::Impl(item) => {
+            iterate_ids(json, files, handled_ids, parent_path, item.items.iter())
+        }
 
         ItemEnum::Typedef(_) => Ok(()),
         ItemEnum::OpaqueTy(_) => Ok(()),
@@ -129,6 +90,8 @@ pub fn iterate_children<'rustdoc>(
 
         ItemEnum::Static(_) => Ok(()),
 
+        // Foreign functions, statics and types are exported as children of the module their `extern` block is in.
+        // rustdoc doesn't link foreign types to their impls, so they don't have any children
         ItemEnum::ForeignType => Ok(()),
 
         ItemEnum::Macro(_) => Ok(()),
@@ -142,17 +105,16 @@ pub fn iterate_children<'rustdoc>(
 }
 
 fn iterate_ids<'rustdoc>(
-    sh: &Shell,
     json: &'rustdoc Crate,
-    output_dir: &PathBuf,
-    handled_ids: &mut Vec<&'rustdoc String>,
+    files: &mut Vec<MarkdownFile<'rustdoc>>,
+    handled_ids: &mut HashSet<&'rustdoc String>,
     parent_path: &Vec<String>,
     ids: impl Iterator<Item = &'rustdoc Id>,
 ) -> Result<(), Box<dyn Error>> {
     for id in ids {
         let item = json.index.get(id).expect("rustdoc JSON output is invalid?");
         let kind = item.inner.to_item_kind();
-        let Some(name) = item.name.to_owned() else {
+        let Some(name) = item_name(item) else {
             trace!(
                 item_kind = debug(kind),
                 span = debug(&item.span),
@@ -165,7 +127,7 @@ fn iterate_ids<'rustdoc>(
         let mut path = parent_path.clone();
         path.push(name);
 
-        handle_item(sh, json, output_dir, handled_ids, id, item, &path, &kind)?;
+        handle_item(json, files, handled_ids, id, item, &path, &kind)?;
     }
 
     Ok(())
@@ -0,0 +1,394 @@
+//! The output directory can be published with a static site generator. The table of contents mirrors the module hierarchy
+//! and the markdown files stay where they are, so the output directory can still be used by from-markdown
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use glob::glob;
+use normalize_path::NormalizePath;
+use tracing::info;
+use xshell::Shell;
+
+use crate::consts::{INDEX_FILE_NAME, SUMMARY_FILE_NAME};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// Only the markdown files.
+    Plain,
+    /// Also generate a `SUMMARY.md` and a `book.toml` in the output directory, so it can be built with `mdbook build <output dir>`.
+    Mdbook,
+    /// Also generate a `mkdocs.yml` next to the output directory (named after it, such as `doc-sync.mkdocs.yml`), so it can be built with `mkdocs build -f <file>`.
+    Mkdocs,
+}
+
+impl Layout {
+    /// Static site generators only publish the output directory, so links to the source files would be broken
+    pub fn links_to_sources(self) -> bool {
+        self == Layout::Plain
+    }
+}
+
+/// A chapter of the table of contents. Chapters without a path are directories without a markdown file, such as the `bin` directory
+struct Chapter {
+    title: String,
+    path: Option<String>,
+    children: Vec<Chapter>,
+}
+
+#[wrap_match::wrap_match(log_success = false)]
+pub fn write_layout(sh: &Shell, output_dir: &Path, layout: Layout) -> Result<(), Box<dyn Error>> {
+    if layout == Layout::Plain {
+        return Ok(());
+    }
+
+    // Paths relative to the output directory, with `/` as separator so they can be used in links
+    let mut files = BTreeSet::new();
+    for file in glob(&format!("{}/**/*.md", output_dir.display()))? {
+        // glob leaves out the `./` the output directory starts with by default
+        let file = file?.normalize();
+        let relative_path = file.strip_prefix(output_dir.normalize())?;
+        let relative_path: Vec<_> = relative_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        files.insert(relative_path.join("/"));
+    }
+    files.remove(SUMMARY_FILE_NAME);
+    let chapters = chapters(&files, "");
+    // The crate roots at the top of the output directory, or the crates in the directories of the target kinds
+    let crates: Vec<_> = match chapters
+        .iter()
+        .filter(|c| c.path.is_some())
+        .collect::<Vec<_>>()
+    {
+        crates if crates.is_empty() => chapters.iter().flat_map(|c| &c.children).collect(),
+        crates => crates,
+    };
+    let title = crates
+        .iter()
+        .map(|c| c.title.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    match layout {
+        Layout::Plain => {}
+        Layout::Mdbook => {
+            info!("Generating {SUMMARY_FILE_NAME} and book.toml");
+            let mut summary = "# Summary\n\n".to_owned();
+            write_summary(&mut summary, &chapters, 0);
+            sh.write_file(output_dir.join(SUMMARY_FILE_NAME), summary)?;
+            // The book is built outside of the output directory, since from-markdown reads every markdown file in it
+            sh.write_file(
+                output_dir.join("book.toml"),
+                format!(
+                    "[book]\ntitle = {}\nsrc = \".\"\n\n[build]\nbuild-dir = {}\n",
+                    toml_string(&title),
+                    toml_string(&format!("../{}", book_dir_name(output_dir)))
+                ),
+            )?;
+        }
+        Layout::Mkdocs => {
+            let config_path = mkdocs_config_path(output_dir);
+            info!("Generating {}", config_path.display());
+            let mut config = format!(
+                "site_name: {}\ndocs_dir: {}\nsite_dir: {}\nnav:\n",
+                yaml_string(&title),
+                yaml_string(&dir_name(output_dir)),
+                yaml_string(&site_dir_name(output_dir))
+            );
+            write_nav(&mut config, &chapters, 1);
+            sh.write_file(config_path, config)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The paths generated from the output directory, including the ones next to it, so they can be left out of the fingerprint of the sources
+pub fn generated_paths(output_dir: &Path) -> Vec<PathBuf> {
+    vec![
+        output_dir.to_path_buf(),
+        output_dir.with_file_name(book_dir_name(output_dir)),
+        mkdocs_config_path(output_dir),
+        output_dir.with_file_name(site_dir_name(output_dir)),
+    ]
+}
+
+/// MkDocs doesn't allow the config file to be in the docs directory, so it is next to the output directory
+fn mkdocs_config_path(output_dir: &Path) -> PathBuf {
+    output_dir.with_file_name(format!("{}.mkdocs.yml", dir_name(output_dir)))
+}
+
+/// The directory next to the output directory mdBook builds the book into
+fn book_dir_name(output_dir: &Path) -> String {
+    format!("{}-book", dir_name(output_dir))
+}
+
+/// The directory next to the output directory MkDocs builds the site into
+fn site_dir_name(output_dir: &Path) -> String {
+    format!("{}-site", dir_name(output_dir))
+}
+
+/// The chapters of the items in `dir` (relative to the output directory, empty for the output directory itself).
+/// The markdown file of an item is next to the directory of its children
+fn chapters(files: &BTreeSet<String>, dir: &str) -> Vec<Chapter> {
+    let prefix = if dir.is_empty() {
+        String::new()
+    } else {
+        format!("{dir}/")
+    };
+    let mut item_files = vec![];
+    let mut subdirs = BTreeSet::new();
+    for file in files {
+        let Some(relative_path) = file.strip_prefix(&prefix) else {
+            continue;
+        };
+        match relative_path.split_once('/') {
+            Some((subdir, _)) => {
+                subdirs.insert(subdir);
+            }
+            None if relative_path != INDEX_FILE_NAME => item_files.push(relative_path),
+            None => {}
+        }
+    }
+    // Modules first, then the other items by name
+    item_files.sort_by_key(|name| (kind(name) != "Module", *name));
+
+    let mut chapters = vec![];
+    for name in &item_files {
+        let stem = name.split('~').next().unwrap();
+        // A glob import is named after the module it imports, so only the first other item with the name gets the directory
+        let owns_subdir = !matches!(kind(name), "Import" | "ExternCrate")
+            && subdirs.contains(stem)
+            && item_files.iter().find(|other| {
+                other.split('~').next() == Some(stem)
+                    && !matches!(kind(other), "Import" | "ExternCrate")
+            }) == Some(name);
+        let children = if owns_subdir {
+            subdirs.remove(stem);
+            subdir_chapters(files, &prefix, stem)
+        } else {
+            vec![]
+        };
+        chapters.push(Chapter {
+            title: title(name),
+            path: Some(format!("{prefix}{name}")),
+            children,
+        });
+    }
+    for subdir in subdirs {
+        chapters.push(Chapter {
+            title: subdir.to_owned(),
+            path: None,
+            children: subdir_chapters(files, &prefix, subdir),
+        });
+    }
+    chapters
+}
+
+/// The chapters of the subdirectory, starting with its index file
+fn subdir_chapters(files: &BTreeSet<String>, prefix: &str, subdir: &str) -> Vec<Chapter> {
+    let subdir = format!("{prefix}{subdir}");
+    let mut chapters = vec![];
+    let index = format!("{subdir}/{INDEX_FILE_NAME}");
+    if files.contains(&index) {
+        chapters.push(Chapter {
+            title: "Overview".to_owned(),
+            path: Some(index),
+            children: vec![],
+        });
+    }
+    chapters.extend(self::chapters(files, &subdir));
+    chapters
+}
+
+fn kind(file_name: &str) -> &str {
+    file_name
+        .trim_end_matches(".md")
+        .split('~')
+        .nth(1)
+        .unwrap_or_default()
+}
+
+/// The name of the item, with the suffix of its variant if it has several
+fn title(file_name: &str) -> String {
+    let mut parts = file_name.trim_end_matches(".md").split('~');
+    let mut title = parts.next().unwrap().to_owned();
+    let variant: Vec<_> = parts.skip(1).collect();
+    if !variant.is_empty() {
+        title.push_str(&format!(" ({})", variant.join("~")));
+    }
+    title
+}
+
+fn write_summary(summary: &mut String, chapters: &[Chapter], depth: usize) {
+    for chapter in chapters {
+        // mdBook links need spaces to be escaped. Chapters without a path are draft chapters
+        let path = chapter
+            .path
+            .as_deref()
+            .unwrap_or_default()
+            .replace(' ', "%20");
+        summary.push_str(&format!(
+            "{}- [{}]({path})\n",
+            "  ".repeat(depth),
+            chapter.title
+        ));
+        write_summary(summary, &chapter.children, depth + 1);
+    }
+}
+
+fn write_nav(config: &mut String, chapters: &[Chapter], depth: usize) {
+    let indent = "  ".repeat(depth);
+    for chapter in chapters {
+        match (&chapter.path, chapter.children.is_empty()) {
+            (Some(path), true) => config.push_str(&format!(
+                "{indent}- {}: {}\n",
+                yaml_string(&chapter.title),
+                yaml_string(path)
+            )),
+            // Sections can't have a page, so the page of the item is the first page of its section
+            (path, _) => {
+                config.push_str(&format!("{indent}- {}:\n", yaml_string(&chapter.title)));
+                if let Some(path) = path {
+                    config.push_str(&format!(
+                        "{indent}  - {}: {}\n",
+                        yaml_string(&chapter.title),
+                        yaml_string(path)
+                    ));
+                }
+                write_nav(config, &chapter.children, depth + 1);
+            }
+        }
+    }
+}
+
+fn dir_name(dir: &Path) -> String {
+    dir.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "doc-sync".to_owned())
+}
+
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_owned()).to_string()
+}
+
+/// Single-quoted YAML strings only need `'` to be escaped
+fn yaml_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(files: &[&str]) -> BTreeSet<String> {
+        files.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn summary_mirrors_the_module_hierarchy() {
+        let files = files(&[
+            "krate~Module.md",
+            "krate/_index.md",
+            "krate/Point~Struct.md",
+            "krate/Point/_index.md",
+            "krate/Point/x~StructField.md",
+            "krate/foo~Function.md",
+            "krate/foo~Function~serde.md",
+            "krate/module~Module.md",
+            "krate/module~Import.md",
+            "krate/module/bar~Function.md",
+        ]);
+        let mut summary = String::new();
+        write_summary(&mut summary, &chapters(&files, ""), 0);
+        assert_eq!(
+            summary,
+            "\
+- [krate](krate~Module.md)
+  - [Overview](krate/_index.md)
+  - [module](krate/module~Module.md)
+    - [bar](krate/module/bar~Function.md)
+  - [Point](krate/Point~Struct.md)
+    - [Overview](krate/Point/_index.md)
+    - [x](krate/Point/x~StructField.md)
+  - [foo](krate/foo~Function.md)
+  - [foo (serde)](krate/foo~Function~serde.md)
+  - [module](krate/module~Import.md)
+"
+        );
+    }
+
+    #[test]
+    fn nav_puts_the_page_of_an_item_first_in_its_section() {
+        let files = files(&[
+            "bin/tool~Module.md",
+            "bin/tool/_index.md",
+            "bin/tool/run~Function.md",
+        ]);
+        let mut nav = String::new();
+        write_nav(&mut nav, &chapters(&files, ""), 0);
+        assert_eq!(
+            nav,
+            "\
+- 'bin':
+  - 'tool':
+    - 'tool': 'bin/tool~Module.md'
+    - 'Overview': 'bin/tool/_index.md'
+    - 'run': 'bin/tool/run~Function.md'
+"
+        );
+    }
+
+    #[test]
+    fn writes_the_config_files_of_each_layout() {
+        let sh = Shell::new().unwrap();
+        let temp_dir = std::env::temp_dir().join(format!("doc-sync-layout-{}", std::process::id()));
+        let output_dir = temp_dir.join("docs");
+        sh.write_file(output_dir.join("krate~Module.md"), "")
+            .unwrap();
+        sh.write_file(output_dir.join("krate/it's~Function.md"), "")
+            .unwrap();
+
+        write_layout(&sh, &output_dir, Layout::Mdbook).unwrap();
+        assert_eq!(
+            sh.read_file(output_dir.join(SUMMARY_FILE_NAME)).unwrap(),
+            "# Summary\n\n- [krate](krate~Module.md)\n  - [it's](krate/it's~Function.md)\n"
+        );
+        assert_eq!(
+            sh.read_file(output_dir.join("book.toml")).unwrap(),
+            "[book]\ntitle = \"krate\"\nsrc = \".\"\n\n[build]\nbuild-dir = \"../docs-book\"\n"
+        );
+
+        write_layout(&sh, &output_dir, Layout::Mkdocs).unwrap();
+        assert_eq!(
+            sh.read_file(temp_dir.join("docs.mkdocs.yml")).unwrap(),
+            "\
+site_name: 'krate'
+docs_dir: 'docs'
+site_dir: 'docs-site'
+nav:
+  - 'krate':
+    - 'krate': 'krate~Module.md'
+    - 'it''s': 'krate/it''s~Function.md'
+"
+        );
+
+        sh.remove_path(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn generated_paths_are_next_to_the_output_directory() {
+        assert_eq!(
+            generated_paths(Path::new("target/doc-sync")),
+            [
+                PathBuf::from("target/doc-sync"),
+                PathBuf::from("target/doc-sync-book"),
+                PathBuf::from("target/doc-sync.mkdocs.yml"),
+                PathBuf::from("target/doc-sync-site"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Markdown files are collected for the JSON of every feature set and merged before being written,
+//! so an item that exists in several feature sets only gets one file
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Component, Path, PathBuf};
+
+use tracing::{debug, info, warn};
+use xshell::Shell;
+
+use crate::consts::*;
+
+use super::index::write_index_files;
+use super::layout::Layout;
+
+/// A markdown file generated for an item of one rustdoc JSON
+pub struct MarkdownFile<'rustdoc> {
+    /// The path of the file relative to the output directory, without the `.md` extension
+    pub path: String,
+    pub id: &'rustdoc str,
+    /// The file and first line of the item, which is the same for every feature set the item is in
+    pub location: Option<(&'rustdoc Path, usize)>,
+    pub docs: String,
+    /// Why the docs can't be synced back to the source, if they can't
+    pub read_only: Option<&'static str>,
+    /// What the item is, shown above the docs. The link to the source is added when the file is written since it depends on where the file is
+    pub header: Option<String>,
+}
+
+/// An item and the feature sets it was found in. The first feature set is the one whose JSON is used for the rustdoc ID
+struct Variant<'rustdoc> {
+    file: MarkdownFile<'rustdoc>,
+    feature_sets: Vec<Option<&'rustdoc str>>,
+}
+
+/// Merges the markdown files generated from the JSON of each feature set and writes them to `output_dir`.
+///
+/// Items at the same location are the same item, so they are merged and the metadata records every feature set that includes them.
+/// Items with the same path but a different location (for example `#[cfg(feature = "a")]` and `#[cfg(not(feature = "a"))]`)
+/// are different variants of the item, so each variant gets its own file with the name of its first feature set appended.
+#[wrap_match::wrap_match(log_success = false)]
+pub fn write_markdown_files<'rustdoc>(
+    sh: &Shell,
+    output_dir: &Path,
+    files_by_feature_set: Vec<(Option<&'rustdoc str>, Vec<MarkdownFile<'rustdoc>>)>,
+    layout: Layout,
+) -> Result<(), Box<dyn Error>> {
+    let variants_by_path = merge_variants(files_by_feature_set);
+
+    let mut written_files = vec![];
+    for (path, variants) in &variants_by_path {
+        if variants.len() > 1 {
+            info!(
+                path,
+                variants = variants.len(),
+                "The item has several variants, each variant will get its own file"
+            );
+        }
+        for (file_path, variant) in variant_file_paths(path, variants).into_iter().zip(variants) {
+            write_markdown_file(sh, output_dir, &file_path, variant, layout)?;
+            written_files.push((file_path, &variant.file));
+        }
+    }
+    write_index_files(sh, output_dir, &written_files)?;
+
+    Ok(())
+}
+
+/// Groups the files by path and merges the files of the same item
+fn merge_variants<'rustdoc>(
+    files_by_feature_set: Vec<(Option<&'rustdoc str>, Vec<MarkdownFile<'rustdoc>>)>,
+) -> BTreeMap<String, Vec<Variant<'rustdoc>>> {
+    let mut variants_by_path: BTreeMap<String, Vec<Variant>> = BTreeMap::new();
+    for (feature_set, files) in files_by_feature_set {
+        for file in files {
+            let variants = variants_by_path.entry(file.path.clone()).or_default();
+            // Two items of the same JSON are never the same item, even if rustdoc gives them the same location
+            match variants.iter_mut().find(|v| {
+                v.file.location == file.location && !v.feature_sets.contains(&feature_set)
+            }) {
+                Some(variant) => {
+                    if variant.file.docs != file.docs {
+                        warn!(
+                            path = file.path,
+                            feature_set, "The docs of the item are different in this feature set, the docs of the first feature set will be used"
+                        );
+                    }
+                    variant.feature_sets.push(feature_set);
+                }
+                None => variants.push(Variant {
+                    file,
+                    feature_sets: vec![feature_set],
+                }),
+            }
+        }
+    }
+    variants_by_path
+}
+
+/// The file path of each variant of the item at `path`. Only items with several variants get a suffix
+fn variant_file_paths(path: &str, variants: &[Variant]) -> Vec<String> {
+    if variants.len() == 1 {
+        return vec![format!("{path}.md")];
+    }
+
+    let mut file_paths: Vec<String> = vec![];
+    for (i, variant) in variants.iter().enumerate() {
+        let suffix = match variant.feature_sets[0] {
+            Some(feature_set) => feature_set.to_owned(),
+            None => (i + 1).to_string(),
+        };
+        let mut file_path = format!("{path}~{suffix}.md");
+        if file_paths.contains(&file_path) {
+            file_path = format!("{path}~{suffix}-{}.md", i + 1);
+        }
+        file_paths.push(file_path);
+    }
+    file_paths
+}
+
+fn write_markdown_file(
+    sh: &Shell,
+    output_dir: &Path,
+    file_path: &str,
+    Variant { file, feature_sets }: &Variant,
+    layout: Layout,
+) -> Result<(), Box<dyn Error>> {
+    let mut metadata = format!("{METADATA_ID_PREFIX}{}{METADATA_ID_SUFFIX}", file.id);
+    let feature_sets: Vec<_> = feature_sets.iter().flatten().copied().collect();
+    if !feature_sets.is_empty() {
+        metadata.push_str(&format!(
+            " {METADATA_FEATURE_SETS_PREFIX}{}{METADATA_FEATURE_SETS_SUFFIX}",
+            feature_sets.join(";")
+        ));
+    }
+    if let Some(read_only) = file.read_only {
+        metadata.push_str(&format!(
+            " {METADATA_READ_ONLY_PREFIX}{read_only}{METADATA_READ_ONLY_SUFFIX}"
+        ));
+    }
+
+    let mut header = String::new();
+    if let Some(item_header) = &file.header {
+        header.push_str(item_header);
+        if let Some((source_file, line)) = file.location {
+            if layout.links_to_sources() {
+                let link = source_link(sh, output_dir, file_path, source_file);
+                header.push_str(&format!(
+                    "\n\nDefined in [{}:{line}]({}#L{line})",
+                    source_file.display(),
+                    link.display()
+                ));
+            } else {
+                header.push_str(&format!(
+                    "\n\nDefined in `{}:{line}`",
+                    source_file.display()
+                ));
+            }
+        }
+        if let Some(read_only) = file.read_only {
+            header.push_str(&format!("\n\n**Read-only:** {read_only}"));
+        }
+        header = format!("{HEADER_START}\n\n{header}\n\n{HEADER_END}\n\n");
+    }
+
+    debug!(file_path, "Generating");
+    sh.write_file(
+        output_dir.join(file_path),
+        format!(
+            "{METADATA_COMMENT_PREFIX}{metadata}{METADATA_COMMENT_SUFFIX}\n\n{header}{}",
+            file.docs
+        ),
+    )?;
+    Ok(())
+}
+
+/// The path of the source file relative to the markdown file, so the link works wherever the crate is.
+/// Falls back to the absolute path if the output directory isn't inside the crate
+fn source_link(sh: &Shell, output_dir: &Path, file_path: &str, source_file: &Path) -> PathBuf {
+    // The number of directories between the crate and the markdown file, if the markdown file is inside of the crate
+    let mut depth = Some(0usize);
+    if let Some(markdown_dir) = output_dir.join(file_path).parent() {
+        for component in markdown_dir.components() {
+            depth = match component {
+                Component::Normal(_) => depth.map(|d| d + 1),
+                Component::CurDir => depth,
+                Component::ParentDir => depth.and_then(|d| d.checked_sub(1)),
+                Component::RootDir | Component::Prefix(_) => None,
+            };
+        }
+    }
+    match depth {
+        Some(depth) if !source_file.is_absolute() => {
+            let mut link: PathBuf = (0..depth).map(|_| Component::ParentDir).collect();
+            link.push(source_file);
+            link
+        }
+        _ => sh.current_dir().join(source_file),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn markdown_file<'a>(path: &str, id: &'a str, line: usize) -> MarkdownFile<'a> {
+        MarkdownFile {
+            path: path.to_owned(),
+            id,
+            location: Some((Path::new("src/lib.rs"), line)),
+            docs: format!("Docs of {id}"),
+            read_only: None,
+            header: None,
+        }
+    }
+
+    #[test]
+    fn merges_items_at_the_same_location() {
+        let variants = merge_variants(vec![
+            (None, vec![markdown_file("krate/foo~Function", "1", 3)]),
+            (
+                Some("serde"),
+                vec![markdown_file("krate/foo~Function", "2", 3)],
+            ),
+        ]);
+        let variants = &variants["krate/foo~Function"];
+        assert_eq!(variants.len(), 1);
+        assert_eq!(variants[0].file.id, "1");
+        assert_eq!(variants[0].feature_sets, [None, Some("serde")]);
+        assert_eq!(
+            variant_file_paths("krate/foo~Function", variants),
+            ["krate/foo~Function.md"]
+        );
+    }
+
+    #[test]
+    fn names_variants_after_their_first_feature_set() {
+        let variants = merge_variants(vec![
+            (Some("a"), vec![markdown_file("krate/foo~Function", "1", 3)]),
+            (Some("b"), vec![markdown_file("krate/foo~Function", "2", 6)]),
+            (
+                Some("a,b"),
+                vec![markdown_file("krate/foo~Function", "3", 3)],
+            ),
+        ]);
+        let variants = &variants["krate/foo~Function"];
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants[0].feature_sets, [Some("a"), Some("a,b")]);
+        assert_eq!(variants[1].feature_sets, [Some("b")]);
+        assert_eq!(
+            variant_file_paths("krate/foo~Function", variants),
+            ["krate/foo~Function~a.md", "krate/foo~Function~b.md"]
+        );
+    }
+
+    #[test]
+    fn items_of_the_same_json_are_different_variants() {
+        // Such as two items rustdoc gives the same location because they are expanded from the same macro call
+        let variants = merge_variants(vec![(
+            None,
+            vec![
+                markdown_file("krate/foo~Function", "1", 3),
+                markdown_file("krate/foo~Function", "2", 3),
+            ],
+        )]);
+        let variants = &variants["krate/foo~Function"];
+        assert_eq!(variants.len(), 2);
+        assert_eq!(
+            variant_file_paths("krate/foo~Function", variants),
+            ["krate/foo~Function~1.md", "krate/foo~Function~2.md"]
+        );
+    }
+
+    #[test]
+    fn variant_file_paths_are_unique() {
+        let variants = merge_variants(vec![(
+            Some("a"),
+            vec![
+                markdown_file("krate/foo~Function", "1", 3),
+                markdown_file("krate/foo~Function", "2", 6),
+            ],
+        )]);
+        assert_eq!(
+            variant_file_paths("krate/foo~Function", &variants["krate/foo~Function"]),
+            ["krate/foo~Function~a.md", "krate/foo~Function~a-2.md"]
+        );
+    }
+
+    #[test]
+    fn links_to_sources_relative_to_the_markdown_file() {
+        let sh = Shell::new().unwrap();
+        assert_eq!(
+            source_link(
+                &sh,
+                Path::new("./target/doc-sync"),
+                "krate/foo~Function.md",
+                Path::new("src/lib.rs")
+            ),
+            Path::new("../../../src/lib.rs")
+        );
+        // The link can't be relative if the output directory is outside of the crate
+        assert_eq!(
+            source_link(
+                &sh,
+                Path::new("../docs"),
+                "krate~Module.md",
+                Path::new("src/lib.rs")
+            ),
+            sh.current_dir().join("src/lib.rs")
+        );
+    }
+}
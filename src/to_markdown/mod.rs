@@ -1,22 +1,49 @@
+use std::collections::{BTreeMap, HashSet};
 use std::error::Error;
+use std::path::{Path, PathBuf};
 
 use cli_failure::bail;
-use rustdoc_types::Crate;
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 use xshell::{cmd, Shell};
 
-use crate::from_markdown::FROM_MARKDOWN_MARKER;
+use crate::from_markdown::{ModuleTree, FROM_MARKDOWN_MARKER};
 use crate::get_crate_name;
+use crate::rustdoc_json::{
+    crate_name, feature_set_name, feature_set_of_json_file, json_file_name, read_rustdoc_json,
+    root_file, rustdoc_json_dir, store_rustdoc_json,
+};
 use crate::ToMarkdown;
 
+mod fingerprint;
+use self::fingerprint::{fingerprint, is_up_to_date, store_fingerprint};
+
 mod handle_item;
-use self::handle_item::handle_item;
+use self::handle_item::{handle_item, split_module_docs};
+
+mod header;
+use self::header::add_headers;
+
+mod index;
 
 mod item_enum_ext;
 
 mod iterate_children;
 
-#[wrap_match::wrap_match(disregard_result = true)]
+mod targets;
+use self::targets::get_target_names;
+pub use self::targets::TargetKind;
+
+mod layout;
+use self::layout::write_layout;
+pub use self::layout::Layout;
+
+mod markdown_file;
+use self::markdown_file::write_markdown_files;
+
+mod read_only;
+use self::read_only::mark_read_only;
+
+#[wrap_match::wrap_match(log_success = false)]
 pub fn to_markdown(
     sh: Shell,
     ToMarkdown {
@@ -25,6 +52,11 @@ pub fn to_markdown(
         rustdoc_arguments,
         output_dir,
         force,
+        rustdoc_json,
+        feature_sets,
+        target_kinds,
+        no_cache,
+        layout,
     }: ToMarkdown,
 ) -> Result<(), Box<dyn Error>> {
     if sh.path_exists(&output_dir) {
@@ -38,49 +70,221 @@ pub fn to_markdown(
         info!("Clearing {output_dir:?}");
         sh.remove_path(&output_dir)?;
     }
+    // Otherwise from-markdown would also read the JSON of feature sets and targets that aren't exported anymore
+    sh.remove_path(rustdoc_json_dir(&output_dir))?;
 
-    info!("Getting crate name");
-    let crate_name = get_crate_name(&sh)?;
-    info!("Crate name is {crate_name}");
+    // The JSON of each crate, the feature set it was generated with and the directory in the output directory it goes in
+    let mut json_files: Vec<(PathBuf, Option<String>, &Path)> = vec![];
+    if let Some(rustdoc_json) = rustdoc_json {
+        info!("Using the passed rustdoc JSON instead of running cargo doc");
+        for path in rustdoc_json {
+            let feature_set = feature_set_of_json_file(&path);
+            json_files.push((path, feature_set, Path::new("")));
+        }
+    } else {
+        const DEFAULT_RUSTDOC_ARGUMENTS: &str =
+            "-Z unstable-options --output-format=json --document-private-items";
+        let rustdoc_arguments = if let Some(rustdoc_arguments) = rustdoc_arguments {
+            rustdoc_arguments + " " + DEFAULT_RUSTDOC_ARGUMENTS
+        } else {
+            DEFAULT_RUSTDOC_ARGUMENTS.to_owned()
+        };
+        let cargo_doc_arguments = cargo_doc_arguments.unwrap_or_default();
 
-    info!("Generating JSON through rustdoc");
-    const DEFAULT_RUSTDOC_ARGUMENTS: &str = "-Z unstable-options --output-format=json --document-private-items";
-    let rustdoc_arguments = if let Some(rustdoc_arguments) = rustdoc_arguments {
-        rustdoc_arguments + " " + DEFAULT_RUSTDOC_ARGUMENTS
+        // Without target kinds, only the target named after the package is exported
+        let targets = match target_kinds {
+            Some(target_kinds) => {
+                let mut targets = vec![];
+                for kind in target_kinds {
+                    let names = get_target_names(&sh, kind)?;
+                    if names.is_empty() {
+                        warn!("The package has no targets of kind {kind:?}");
+                    } else {
+                        targets.push((Some(kind), names));
+                    }
+                }
+                targets
+            }
+            None => {
+                info!("Getting crate name");
+                let crate_name = get_crate_name(&sh)?;
+                info!("Crate name is {crate_name}");
+                vec![(None, vec![crate_name])]
+            }
+        };
+        let feature_sets = match feature_sets {
+            Some(feature_sets) => feature_sets.into_iter().map(Some).collect(),
+            None => vec![None],
+        };
+
+        for features in feature_sets {
+            let feature_set = features.as_deref().map(feature_set_name);
+            if let Some(feature_set) = &feature_set {
+                info!("Generating JSON for feature set {feature_set}");
+            }
+            let mut cargo_doc_arguments = cargo_doc_arguments.clone();
+            if let Some(features) = features.filter(|f| !f.trim().is_empty()) {
+                cargo_doc_arguments.extend(["--features".to_owned(), features]);
+            }
+            for (kind, names) in &targets {
+                let mut cargo_doc_arguments = cargo_doc_arguments.clone();
+                if let Some(kind) = kind {
+                    cargo_doc_arguments.push(kind.cargo_doc_argument().to_owned());
+                }
+                let cargo_doc = CargoDoc {
+                    cargo_arguments: &cargo_arguments,
+                    cargo_doc_arguments: &cargo_doc_arguments,
+                    rustdoc_arguments: &rustdoc_arguments,
+                    no_cache,
+                };
+                let subdirectory = kind.map_or(Path::new(""), |k| k.subdirectory());
+                for path in generate_rustdoc_json(
+                    &sh,
+                    &output_dir,
+                    names,
+                    *kind,
+                    feature_set.as_deref(),
+                    cargo_doc,
+                )? {
+                    json_files.push((path, feature_set.clone(), subdirectory));
+                }
+            }
+        }
+    }
+    let json_paths: Vec<_> = json_files.iter().map(|(path, ..)| path.clone()).collect();
+    let crates = read_rustdoc_json(&sh, &json_paths)?;
+
+    // The JSON of every feature set of a crate is merged, so group them by crate.
+    // Each kind of target has its own directory, since a binary can have the same name as the library.
+    let mut crates_by_name: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for (json, (_, feature_set, subdirectory)) in crates.iter().zip(&json_files) {
+        crates_by_name
+            .entry((subdirectory, crate_name(json)))
+            .or_default()
+            .push((feature_set.as_deref(), json));
+    }
+
+    for ((subdirectory, crate_name), jsons) in crates_by_name {
+        info!("Generating markdown from JSON for {crate_name}");
+        let markdown_dir = output_dir.join(subdirectory);
+        // Each crate gets its own subtree since the first part of each path is the crate name
+        let mut files_by_feature_set = vec![];
+        for (feature_set, json) in &jsons {
+            let mut files = vec![];
+            let mut handled_ids = HashSet::new();
+            for (id, item) in json.paths.iter().filter(|(_, i)| i.crate_id == 0) {
+                let Some(indexed_item) = json.index.get(id) else {
+                    // rustdoc gives derive macros a different ID in the paths than in the index. They are still found through their module
+                    debug!(
+                        id = id.0,
+                        path = debug(&item.path),
+                        "Skipping item that is in the paths but not in the index"
+                    );
+                    continue;
+                };
+                handle_item(
+                    json,
+                    &mut files,
+                    &mut handled_ids,
+                    id,
+                    indexed_item,
+                    &item.path,
+                    &item.kind,
+                )?;
+            }
+            let modules = root_file(json)
+                .map(|root_file| ModuleTree::new(&sh, root_file))
+                .unwrap_or_default();
+            split_module_docs(json, &modules, &mut files);
+            mark_read_only(&sh, json, &modules, &mut files);
+            add_headers(&sh, json, &mut files);
+            files_by_feature_set.push((*feature_set, files));
+        }
+        write_markdown_files(&sh, &markdown_dir, files_by_feature_set, layout)?;
+
+        for (feature_set, json) in jsons {
+            store_rustdoc_json(&sh, &output_dir, subdirectory, json, feature_set)?;
+        }
+    }
+    write_layout(&sh, &output_dir, layout)?;
+
+    Ok(())
+}
+
+/// How to run cargo doc
+#[derive(Clone, Copy)]
+struct CargoDoc<'a> {
+    cargo_arguments: &'a str,
+    cargo_doc_arguments: &'a [String],
+    rustdoc_arguments: &'a str,
+    no_cache: bool,
+}
+
+/// Runs cargo doc unless the JSON of every crate is up to date and returns the paths of the JSON.
+/// JSON generated for a feature set or a target kind is copied to its own file, so the JSON of each of them can be cached.
+#[wrap_match::wrap_match(log_success = false)]
+fn generate_rustdoc_json(
+    sh: &Shell,
+    output_dir: &Path,
+    crate_names: &[String],
+    kind: Option<TargetKind>,
+    feature_set: Option<&str>,
+    CargoDoc {
+        cargo_arguments,
+        cargo_doc_arguments,
+        rustdoc_arguments,
+        no_cache,
+    }: CargoDoc,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let rustdoc_output_dir = Path::new("./target/doc");
+    let json_dir = match kind {
+        Some(kind) => rustdoc_output_dir.join("doc-sync").join(kind.name()),
+        None => rustdoc_output_dir.to_path_buf(),
+    };
+    let json_paths: Vec<_> = crate_names
+        .iter()
+        .map(|name| json_dir.join(json_file_name(name, feature_set)))
+        .collect();
+
+    // Hashing the package can take a while, so it is skipped when the JSON is always generated again
+    let fingerprint = if no_cache {
+        None
     } else {
-        DEFAULT_RUSTDOC_ARGUMENTS.to_owned()
+        Some(fingerprint(
+            sh,
+            output_dir,
+            cargo_arguments,
+            cargo_doc_arguments,
+            rustdoc_arguments,
+        )?)
     };
-    println!("rustdoc arguments: \"{rustdoc_arguments}\"");
-    let cargo_doc_arguments = cargo_doc_arguments.unwrap_or_default();
-    cmd!(
-        sh,
-        "cargo {cargo_arguments} doc --no-deps {cargo_doc_arguments...}"
-    )
-    .env("RUSTDOCFLAGS", rustdoc_arguments)
-    .env_remove("RUSTFLAGS")
-    .run()?;
-
-    let json_path = format!("./target/doc/{crate_name}.json");
-    info!("Reading outputted JSON from {json_path}");
-    let json = sh.read_file(&json_path)?;
-
-    info!("Deserializing JSON");
-    let json: Crate = serde_json::from_str(&json)?;
-
-    info!("Generating markdown from JSON");
-    let mut handled_ids = vec![];
-    for (id, item) in json.paths.iter().filter(|(_, i)| i.crate_id == 0) {
-        handle_item(
-            &sh,
-            &json,
-            &output_dir,
-            &mut handled_ids,
-            id,
-            json.index.get(id).expect("rustdoc JSON output is invalid?"),
-            &item.path,
-            &item.kind,
-        )?;
+    if fingerprint.as_ref().is_some_and(|fingerprint| {
+        json_paths
+            .iter()
+            .all(|path| is_up_to_date(sh, path, fingerprint))
+    }) {
+        info!("Sources, toolchain and arguments haven't changed since the rustdoc JSON was generated, so cargo doc will not be run");
+    } else {
+        info!("Generating JSON through rustdoc");
+        debug!("rustdoc arguments: \"{rustdoc_arguments}\"");
+        cmd!(
+            sh,
+            "cargo {cargo_arguments} doc --no-deps {cargo_doc_arguments...}"
+        )
+        .env("RUSTDOCFLAGS", rustdoc_arguments)
+        .env_remove("RUSTFLAGS")
+        .run()?;
+        sh.create_dir(&json_dir)?;
+        for (name, json_path) in crate_names.iter().zip(&json_paths) {
+            let rustdoc_output_path = rustdoc_output_dir.join(format!("{name}.json"));
+            if *json_path != rustdoc_output_path {
+                sh.copy_file(&rustdoc_output_path, json_path)?;
+            }
+            if let Some(fingerprint) = &fingerprint {
+                store_fingerprint(sh, json_path, fingerprint)?;
+            }
+        }
     }
 
-    Ok(())
+    Ok(json_paths)
 }
@@ -0,0 +1,398 @@
+//! Some docs can't be written back to the source, for example because the item is expanded from a macro.
+//! The markdown files of these items are marked as read-only, so from-markdown can refuse edits to them instead of editing the wrong place
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use normalize_path::NormalizePath;
+use regex::Regex;
+use rustdoc_types::{Crate, Id, Item, ItemEnum, Span};
+use syn::spanned::Spanned;
+use syn::UseTree;
+use tracing::{debug, warn};
+use xshell::Shell;
+
+use crate::from_markdown::ModuleTree;
+
+use super::markdown_file::MarkdownFile;
+
+const GENERATED_FILE: &str = "The item is in a generated file (such as a file included from OUT_DIR), so its docs would be lost the next time the file is generated";
+const MACRO_EXPANSION: &str =
+    "The item is expanded from a macro, so its docs can't be edited where the item is";
+const DOC_MACRO: &str = "The docs of the item are generated by a macro in a #[doc = ...] attribute (such as concat!, env! or include_str!), so they can't be edited in place";
+const SHARED_USE: &str = "The item is imported by a `use` that imports several names, which share one doc comment, so editing its docs would change the docs of the other names too";
+
+/// A line that starts with a macro call, such as `my_macro! {`
+static MACRO_CALL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(?:::)?[A-Za-z_]\w*(?:::[A-Za-z_]\w*)*\s*!\s*[(\[{]").unwrap());
+/// The start of a `#[doc = ...]` or `#![doc = ...]` attribute with a macro
+static DOC_MACRO_ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"#\s*(!)?\s*\[\s*doc\s*=\s*(?:::)?[A-Za-z_]\w*(?:::[A-Za-z_]\w*)*\s*!").unwrap()
+});
+
+/// The parts of a source file that make the docs of items in it read-only
+struct SourceFile {
+    contents: String,
+    /// The lines of the items that have a `#[doc = ...]` attribute with a macro
+    doc_macro_lines: HashSet<usize>,
+    /// The lines of the `#![doc = ...]` attributes with a macro, which are in the module they document
+    inner_doc_macro_lines: Vec<usize>,
+    /// The first and last line of every `use` that imports more than one name
+    shared_uses: Vec<(usize, usize)>,
+}
+
+pub fn mark_read_only(sh: &Shell, json: &Crate, modules: &ModuleTree, files: &mut [MarkdownFile]) {
+    let crate_dir = sh.current_dir();
+    let macros: Vec<_> = json
+        .index
+        .values()
+        .filter(|i| i.crate_id == 0 && matches!(i.inner, ItemEnum::Macro(_)))
+        .filter_map(|i| i.span.as_ref())
+        .collect();
+    let mut sources: HashMap<PathBuf, Option<SourceFile>> = HashMap::new();
+
+    for file in files {
+        let Some(item) = json.index.get(&Id(file.id.to_owned())) else {
+            continue;
+        };
+        let Some(span) = &item.span else {
+            continue;
+        };
+        let source = sources.entry(span.filename.clone()).or_insert_with(|| {
+            match sh.read_file(&span.filename) {
+                Ok(contents) => Some(SourceFile::new(contents)),
+                Err(e) => {
+                    warn!(
+                        file = display(span.filename.display()),
+                        "Couldn't read source file to check whether its docs can be synced: {e}"
+                    );
+                    None
+                }
+            }
+        });
+        let reason = if is_generated(&span.filename, &crate_dir) {
+            Some(GENERATED_FILE)
+        } else if is_macro_expansion(item, span, &macros, source.as_ref()) {
+            Some(MACRO_EXPANSION)
+        } else if has_doc_macro(json, item, span, modules, source.as_ref()) {
+            Some(DOC_MACRO)
+        } else if is_in_shared_use(item, span, source.as_ref()) {
+            Some(SHARED_USE)
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            debug!(path = file.path, reason, "Marking as read-only");
+            file.read_only = Some(reason);
+        }
+    }
+}
+
+/// Files outside of the crate directory or in `target` are generated, for example by a build script
+fn is_generated(file: &Path, crate_dir: &Path) -> bool {
+    let file = crate_dir.join(file).normalize();
+    !file.starts_with(crate_dir) || file.starts_with(crate_dir.join("target"))
+}
+
+/// rustdoc gives items expanded from a macro the span of the macro call, or a span in the `macro_rules!` the item is written in
+fn is_macro_expansion(
+    item: &Item,
+    span: &Span,
+    macros: &[&Span],
+    source: Option<&SourceFile>,
+) -> bool {
+    let in_macro_definition = macros.iter().any(|macro_span| {
+        *macro_span != span
+            && macro_span.filename == span.filename
+            && macro_span.begin.0 <= span.begin.0
+            && span.end.0 <= macro_span.end.0
+    });
+    if in_macro_definition {
+        return true;
+    }
+    if matches!(item.inner, ItemEnum::Macro(_)) {
+        return false;
+    }
+    source
+        .and_then(|source| source.contents.lines().nth(span.begin.0.saturating_sub(1)))
+        .is_some_and(|line| MACRO_CALL.is_match(line.trim_start()))
+}
+
+fn has_doc_macro(
+    json: &Crate,
+    item: &Item,
+    span: &Span,
+    modules: &ModuleTree,
+    source: Option<&SourceFile>,
+) -> bool {
+    let Some(source) = source else {
+        return false;
+    };
+    if source.doc_macro_lines.contains(&span.begin.0) {
+        return true;
+    }
+    // Inner attributes document the innermost module they are in
+    let (ItemEnum::Module(_), Some(summary)) = (&item.inner, json.paths.get(&item.id)) else {
+        return false;
+    };
+    let file = span.filename.display().to_string();
+    source
+        .inner_doc_macro_lines
+        .iter()
+        .any(|line| summary.path.get(1..) == Some(modules.module_path_at(&file, *line).as_slice()))
+}
+
+/// rustdoc has an item for every name a `use` imports, but they all get the docs of the `use`
+fn is_in_shared_use(item: &Item, span: &Span, source: Option<&SourceFile>) -> bool {
+    matches!(item.inner, ItemEnum::Import(_))
+        && source.is_some_and(|source| {
+            source
+                .shared_uses
+                .iter()
+                .any(|(first, last)| (*first..=*last).contains(&span.begin.0))
+        })
+}
+
+impl SourceFile {
+    fn new(contents: String) -> SourceFile {
+        let mut doc_macro_lines = HashSet::new();
+        let mut inner_doc_macro_lines = vec![];
+        for captures in DOC_MACRO_ATTRIBUTE.captures_iter(&contents) {
+            let attr = captures.get(0).unwrap();
+            if captures.get(1).is_some() {
+                inner_doc_macro_lines.push(line_of(&contents, attr.start()));
+                continue;
+            }
+            // The item starts after the attribute and the attributes and comments after it
+            let Some(attr_end) = skip_brackets(&contents, attr.end()) else {
+                continue;
+            };
+            let item_start = skip_attributes_and_comments(&contents, attr_end);
+            doc_macro_lines.insert(line_of(&contents, item_start));
+        }
+        let mut shared_uses = vec![];
+        if let Ok(file) = syn::parse_file(&contents) {
+            find_shared_uses(&file.items, &mut shared_uses);
+        }
+        SourceFile {
+            contents,
+            doc_macro_lines,
+            inner_doc_macro_lines,
+            shared_uses,
+        }
+    }
+}
+
+/// Also finds the `use`s in inline modules, since rustdoc documents those too
+fn find_shared_uses(items: &[syn::Item], shared_uses: &mut Vec<(usize, usize)>) {
+    for item in items {
+        match item {
+            syn::Item::Use(item) if import_count(&item.tree) > 1 => {
+                let span = item.span();
+                shared_uses.push((span.start().line, span.end().line));
+            }
+            syn::Item::Mod(syn::ItemMod {
+                content: Some((_, items)),
+                ..
+            }) => find_shared_uses(items, shared_uses),
+            _ => {}
+        }
+    }
+}
+
+/// The number of names the use tree imports. A glob import counts as one name, like in rustdoc
+fn import_count(tree: &UseTree) -> usize {
+    match tree {
+        UseTree::Path(tree) => import_count(&tree.tree),
+        UseTree::Name(_) | UseTree::Rename(_) | UseTree::Glob(_) => 1,
+        UseTree::Group(tree) => tree.items.iter().map(import_count).sum(),
+    }
+}
+
+/// The 1-based line of the byte position, like rustdoc's spans
+fn line_of(contents: &str, pos: usize) -> usize {
+    contents[..pos].matches('\n').count() + 1
+}
+
+/// Returns the position after the `]` that closes the bracket `pos` is in
+fn skip_brackets(contents: &str, mut pos: usize) -> Option<usize> {
+    let bytes = contents.as_bytes();
+    let mut depth = 1;
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'"' => {
+                pos += 1;
+                while pos < bytes.len() && bytes[pos] != b'"' {
+                    if bytes[pos] == b'\\' {
+                        pos += 1;
+                    }
+                    pos += 1;
+                }
+            }
+            b'[' => depth += 1,
+            b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(pos + 1);
+                }
+            }
+            _ => {}
+        }
+        pos += 1;
+    }
+    None
+}
+
+fn skip_attributes_and_comments(contents: &str, mut pos: usize) -> usize {
+    loop {
+        let rest = &contents[pos..];
+        let trimmed = rest.trim_start();
+        pos += rest.len() - trimmed.len();
+        if trimmed.starts_with("//") {
+            pos += trimmed.find('\n').unwrap_or(trimmed.len());
+        } else if trimmed.starts_with("/*") {
+            pos += trimmed.find("*/").map_or(trimmed.len(), |end| end + 2);
+        } else if trimmed.starts_with('#') && trimmed[1..].trim_start().starts_with('[') {
+            let bracket = pos + trimmed.find('[').unwrap() + 1;
+            match skip_brackets(contents, bracket) {
+                Some(end) => pos = end,
+                None => return pos,
+            }
+        } else {
+            return pos;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustdoc_types::{Generics, Struct, StructKind};
+
+    use super::*;
+    use crate::from_markdown::test_utils::rustdoc_item;
+
+    fn span(begin: usize, end: usize) -> Span {
+        Span {
+            filename: PathBuf::from("src/lib.rs"),
+            begin: (begin, 0),
+            end: (end, 0),
+        }
+    }
+
+    fn unit_struct() -> Item {
+        rustdoc_item(
+            "0:1",
+            Some("Made"),
+            ItemEnum::Struct(Struct {
+                kind: StructKind::Unit,
+                generics: Generics {
+                    params: vec![],
+                    where_predicates: vec![],
+                },
+                impls: vec![],
+            }),
+        )
+    }
+
+    #[test]
+    fn finds_items_expanded_from_macros() {
+        let source = SourceFile::new(
+            "\
+macro_rules! make_struct {
+    () => {
+        pub struct Made;
+    };
+}
+make_struct!();
+  crate::other::make_struct! { }
+pub struct Foo;
+"
+            .to_owned(),
+        );
+        let macro_span = span(1, 5);
+        let macros = [&macro_span];
+        let is_expansion =
+            |item: &Item, span: &Span| is_macro_expansion(item, span, &macros, Some(&source));
+        let item = unit_struct();
+        assert!(is_expansion(&item, &span(3, 3)));
+        assert!(is_expansion(&item, &span(6, 6)));
+        assert!(is_expansion(&item, &span(7, 7)));
+        assert!(!is_expansion(&item, &span(8, 8)));
+
+        // A macro isn't expanded from itself, and macro definitions aren't macro calls
+        let macro_item = rustdoc_item(
+            "0:2",
+            Some("make_struct"),
+            ItemEnum::Macro("macro_rules! make_struct { ... }".to_owned()),
+        );
+        assert!(!is_expansion(&macro_item, &macro_span));
+    }
+
+    #[test]
+    fn finds_docs_generated_by_macros() {
+        let source = SourceFile::new(
+            "\
+#![doc = include_str!(\"../README.md\")]
+
+#[doc = concat!(\"Docs with a ]\", \"bracket\")]
+// A comment
+#[derive(Debug)] /* and a block comment */
+pub struct Foo;
+
+#[doc = \"Literal docs\"]
+pub struct Bar;
+
+# [doc = ::std::env!(\"DOCS\")]
+pub struct Baz;
+"
+            .to_owned(),
+        );
+        assert_eq!(source.inner_doc_macro_lines, [1]);
+        assert_eq!(source.doc_macro_lines, HashSet::from([6, 12]));
+    }
+
+    #[test]
+    fn skips_attributes_and_comments() {
+        let contents = "  #[a(\"]\")]\n// comment\n/* block */ # [b]\nfn foo() {}";
+        assert_eq!(
+            &contents[skip_attributes_and_comments(contents, 0)..],
+            "fn foo() {}"
+        );
+    }
+
+    #[test]
+    fn files_outside_of_the_crate_or_in_target_are_generated() {
+        let crate_dir = Path::new("/krate");
+        assert!(!is_generated(Path::new("src/lib.rs"), crate_dir));
+        assert!(!is_generated(Path::new("/krate/src/lib.rs"), crate_dir));
+        assert!(is_generated(
+            Path::new("target/debug/build/krate-0123/out/generated.rs"),
+            crate_dir
+        ));
+        assert!(is_generated(Path::new("../other/src/lib.rs"), crate_dir));
+        assert!(is_generated(
+            Path::new("/home/user/.cargo/registry/src/lib.rs"),
+            crate_dir
+        ));
+    }
+
+    #[test]
+    fn finds_uses_that_import_several_names() {
+        let source = SourceFile::new(
+            "\
+use a::b;
+pub use a::{b as c, d};
+mod inline {
+    pub use e::{
+        f::*,
+        g,
+    };
+    use e::{h};
+}
+"
+            .to_owned(),
+        );
+        assert_eq!(source.shared_uses, [(2, 2), (4, 7)]);
+    }
+}
@@ -0,0 +1,120 @@
+use std::error::Error;
+use std::path::Path;
+
+use clap::ValueEnum;
+use cli_failure::failure_raw;
+use normalize_path::NormalizePath;
+use serde_json::Value;
+use tracing::debug;
+use xshell::{cmd, Shell};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TargetKind {
+    /// The library of the package.
+    Lib,
+    /// Every binary of the package. The markdown files are put in the `bin` directory of the output directory.
+    Bin,
+    /// Every example of the package. The markdown files are put in the `example` directory of the output directory.
+    Example,
+}
+
+impl TargetKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            TargetKind::Lib => "lib",
+            TargetKind::Bin => "bin",
+            TargetKind::Example => "example",
+        }
+    }
+
+    pub fn cargo_doc_argument(self) -> &'static str {
+        match self {
+            TargetKind::Lib => "--lib",
+            TargetKind::Bin => "--bins",
+            TargetKind::Example => "--examples",
+        }
+    }
+
+    /// The directory in the output directory for targets of this kind. A binary can have the same name as the library, so they each need their own directory
+    pub fn subdirectory(self) -> &'static Path {
+        match self {
+            TargetKind::Lib => Path::new(""),
+            kind => Path::new(kind.name()),
+        }
+    }
+
+    /// Whether a target with these kinds from `cargo metadata` is of this kind
+    fn matches(self, kinds: &[Value]) -> bool {
+        kinds
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|kind| match self {
+                TargetKind::Lib => {
+                    matches!(
+                        kind,
+                        "lib" | "rlib" | "dylib" | "cdylib" | "staticlib" | "proc-macro"
+                    )
+                }
+                TargetKind::Bin => kind == "bin",
+                TargetKind::Example => kind == "example",
+            })
+    }
+}
+
+/// The names of the targets of the package in the current directory with the kind, as used by rustdoc (with dashes replaced with underscores)
+#[wrap_match::wrap_match(log_success = false)]
+pub fn get_target_names(sh: &Shell, kind: TargetKind) -> Result<Vec<String>, Box<dyn Error>> {
+    let metadata = cmd!(sh, "cargo metadata --no-deps --format-version 1").read()?;
+    let metadata: Value = serde_json::from_str(&metadata)?;
+    let manifest_path = sh.current_dir().join("Cargo.toml").normalize();
+    let package = metadata["packages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|p| {
+            p["manifest_path"]
+                .as_str()
+                .is_some_and(|m| Path::new(m).normalize() == manifest_path)
+        })
+        .ok_or_else(|| {
+            failure_raw!("cargo metadata doesn't contain the package in the current directory")
+        })?;
+
+    let names: Vec<_> = package["targets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|t| t["kind"].as_array().is_some_and(|k| kind.matches(k)))
+        .filter_map(|t| t["name"].as_str())
+        .map(|name| name.replace('-', "_"))
+        .collect();
+    debug!(kind = debug(kind), names = debug(&names));
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn matches_the_kinds_of_cargo_metadata() {
+        let kinds = |kinds: Value| kinds.as_array().unwrap().clone();
+        assert!(TargetKind::Lib.matches(&kinds(json!(["lib"]))));
+        assert!(TargetKind::Lib.matches(&kinds(json!(["cdylib", "rlib"]))));
+        assert!(TargetKind::Lib.matches(&kinds(json!(["proc-macro"]))));
+        assert!(!TargetKind::Lib.matches(&kinds(json!(["bin"]))));
+        assert!(TargetKind::Bin.matches(&kinds(json!(["bin"]))));
+        assert!(!TargetKind::Bin.matches(&kinds(json!(["example"]))));
+        assert!(TargetKind::Example.matches(&kinds(json!(["example"]))));
+        assert!(!TargetKind::Example.matches(&kinds(json!(["lib"]))));
+    }
+
+    #[test]
+    fn only_the_library_is_in_the_output_directory_itself() {
+        assert_eq!(TargetKind::Lib.subdirectory(), Path::new(""));
+        assert_eq!(TargetKind::Bin.subdirectory(), Path::new("bin"));
+        assert_eq!(TargetKind::Example.subdirectory(), Path::new("example"));
+    }
+}
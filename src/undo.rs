@@ -0,0 +1,54 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use cli_failure::bail;
+use tracing::{info, warn};
+use xshell::Shell;
+
+use crate::from_markdown::FROM_MARKDOWN_MARKER;
+use crate::journal::{read_journal, remove_journal, JOURNAL_DIR};
+use crate::write_files::{write_files, PendingFile};
+use crate::Undo;
+
+#[wrap_match::wrap_match(log_success = false)]
+pub fn undo(sh: Shell, Undo { force }: Undo) -> Result<(), Box<dyn Error>> {
+    let Some((input_dir, entries)) = read_journal(&sh)? else {
+        bail!("There is nothing to undo; no journal was found in {JOURNAL_DIR}");
+    };
+
+    let mut changed_files = vec![];
+    let mut files = BTreeMap::new();
+    for entry in entries {
+        let current_contents = sh.read_file(&entry.file_path)?;
+        if current_contents != entry.modified_contents {
+            changed_files.push(entry.file_path.clone());
+        }
+        files.insert(
+            entry.file_path,
+            PendingFile {
+                original_contents: current_contents,
+                new_contents: entry.original_contents,
+            },
+        );
+    }
+
+    if !changed_files.is_empty() {
+        if force {
+            warn!(changed_files = debug(&changed_files), "Some files have been changed since from-markdown modified them, but `--force` was passed so doc-sync will continue. These changes will be lost.");
+        } else {
+            bail!("Some files have been changed since from-markdown modified them: {changed_files:?}. Undoing would lose these changes. To bypass this, use the `--force` command line argument.");
+        }
+    }
+
+    info!("Restoring {} files", files.len());
+    write_files(&sh, &files)?;
+
+    // The markdown files now contain docs that aren't in the source files anymore, so to-markdown shouldn't overwrite them
+    let marker = input_dir.join(FROM_MARKDOWN_MARKER);
+    if sh.path_exists(&marker) {
+        sh.remove_path(&marker)?;
+    }
+    remove_journal(&sh)?;
+
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use tracing::{debug, error, info};
+use xshell::Shell;
+
+/// Appended to the path of a file to get the temporary file its new contents are written to before it's replaced
+const TEMP_FILE_SUFFIX: &str = ".doc-sync-tmp";
+
+/// Writes all files at once. Each file is first written to a temporary file next to it, which then atomically replaces it.
+/// If anything fails, the files that were already replaced are restored, so either all files change or none of them do.
+#[tracing::instrument(skip(sh, files))]
+pub fn write_files(
+    sh: &Shell,
+    files: &BTreeMap<String, PendingFile>,
+) -> Result<(), Box<dyn Error>> {
+    let mut temp_files = vec![];
+    for (file_path, file) in files {
+        let path = sh.current_dir().join(file_path);
+        let mut temp_path = path.clone().into_os_string();
+        temp_path.push(TEMP_FILE_SUFFIX);
+        let temp_path = PathBuf::from(temp_path);
+        debug!(
+            file_path,
+            temp_path = display(temp_path.display()),
+            "Writing temporary file"
+        );
+        let result = write_temp_file(&path, &temp_path, &file.new_contents);
+        temp_files.push((file_path, path, temp_path));
+        if let Err(e) = result {
+            remove_temp_files(&temp_files);
+            return Err(e.into());
+        }
+    }
+
+    info!("Replacing {} files", temp_files.len());
+    for (i, (file_path, path, temp_path)) in temp_files.iter().enumerate() {
+        if let Err(e) = fs::rename(temp_path, path) {
+            error!(
+                file_path,
+                "Failed to replace file, restoring the files that were already replaced"
+            );
+            for (file_path, path, _) in &temp_files[..i] {
+                if let Err(e) = fs::write(path, &files[*file_path].original_contents) {
+                    error!(file_path, "Failed to restore file: {e:?}");
+                }
+            }
+            remove_temp_files(&temp_files[i..]);
+            return Err(e.into());
+        }
+    }
+
+    Ok(())
+}
+
+fn write_temp_file(path: &Path, temp_path: &Path, contents: &str) -> io::Result<()> {
+    fs::write(temp_path, contents)?;
+    // Keep the permissions of the original file
+    fs::set_permissions(temp_path, fs::metadata(path)?.permissions())
+}
+
+fn remove_temp_files(temp_files: &[(&String, PathBuf, PathBuf)]) {
+    for (_, _, temp_path) in temp_files {
+        // The temporary file may not exist if writing it failed
+        let _ = fs::remove_file(temp_path);
+    }
+}
+
+/// A source file that will be written once all edits have been planned and validated
+pub struct PendingFile {
+    pub original_contents: String,
+    pub new_contents: String,
+}